@@ -0,0 +1,342 @@
+//! Quick looks at A3DA files without writing a program.
+//!
+//! ```text
+//! a3da inspect <file>
+//! a3da validate <file>...
+//! a3da format [--precision <digits>] <file>...
+//! a3da diff <old> <new>
+//! a3da merge <base> <ours> <theirs>
+//! a3da to-json [--strings] <file>
+//! a3da from-json <file>
+//! a3da gltf <file> <out.gltf|out.glb>
+//! a3da from-vmd <file.vmd> <out.a3da>
+//! a3da to-vmd <file> <out.vmd>
+//! a3da csv <file> [<pattern>...]
+//! ```
+//!
+//! `to-json` and `from-json` need the `json` feature, which is on by default,
+//! and `gltf` needs the `gltf` feature.
+
+use serde_a3da::{
+    decode, diff, encode, from_str_lenient, to_string_with, A3da, Document, KeyGlob, LineEnding,
+    SerializerOptions, TextEncoding, Value,
+};
+
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process;
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+const USAGE: &str = "usage:
+    a3da inspect <file>      print the header, versions, section counts and play_control
+    a3da validate <file>...  check files against the typed model
+    a3da format [--precision <digits>] <file>...
+                             rewrite files in canonical key order, rounding
+                             floats to <digits> significant digits if given;
+                             files with duplicate keys or malformed lines
+                             are reported and left alone
+    a3da diff <old> <new>    list changed keys and keyframes between two files
+    a3da merge <base> <ours> <theirs>
+                             merge the changes in theirs into ours, reporting
+                             conflicts
+    a3da to-json [--strings] <file>
+                             print a file as JSON, with numbers kept as strings
+                             if --strings is given (requires the json feature)
+    a3da from-json <file>    print a JSON file made by to-json as A3DA
+                             (requires the json feature)
+    a3da gltf <file> <out.gltf|out.glb>
+                             export the cameras and objects of a file to glTF
+                             (requires the gltf feature)
+    a3da from-vmd <file.vmd> <out.a3da>
+                             convert an MMD camera motion to an A3DA camera
+    a3da to-vmd <file> <out.vmd>
+                             convert the first camera of a file to an MMD
+                             camera motion
+    a3da csv <file> [<pattern>...]
+                             print the curves matching key patterns such as
+                             '**.trans.*' per frame as CSV, or every curve
+                             if none are given";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.split_first() {
+        Some((command, files)) if !files.is_empty() => match command.as_str() {
+            "inspect" if files.len() == 1 => inspect(&files[0]),
+            "validate" => validate(files),
+            "format" => match files {
+                [flag, precision, paths @ ..] if flag == "--precision" && !paths.is_empty() => {
+                    match precision.parse() {
+                        Ok(precision) => format(paths, Some(precision)),
+                        Err(_) => usage(),
+                    }
+                }
+                _ => format(files, None),
+            },
+            "diff" => match files {
+                [old, new] => compare(old, new),
+                _ => usage(),
+            },
+            "merge" => match files {
+                [base, ours, theirs] => merge(base, ours, theirs),
+                _ => usage(),
+            },
+            #[cfg(feature = "json")]
+            "to-json" => match files {
+                [file] => to_json(file, true),
+                [flag, file] if flag == "--strings" => to_json(file, false),
+                _ => usage(),
+            },
+            #[cfg(feature = "json")]
+            "from-json" if files.len() == 1 => from_json(&files[0]),
+            #[cfg(feature = "gltf")]
+            "gltf" => match files {
+                [file, out] => gltf(file, out),
+                _ => usage(),
+            },
+            "from-vmd" => match files {
+                [file, out] => from_vmd(file, out),
+                _ => usage(),
+            },
+            "csv" => csv(&files[0], &files[1..]),
+            "to-vmd" => match files {
+                [file, out] => to_vmd(file, out),
+                _ => usage(),
+            },
+            _ => usage(),
+        },
+        _ => usage(),
+    };
+    match result {
+        Ok(true) => {}
+        Ok(false) => process::exit(1),
+        // A reader such as `head` that stops early is not a failure.
+        Err(err)
+            if err.downcast_ref::<io::Error>().map(io::Error::kind)
+                == Some(io::ErrorKind::BrokenPipe) => {}
+        Err(err) => {
+            eprintln!("error: {}", err);
+            process::exit(2);
+        }
+    }
+}
+
+fn usage() -> Result<bool> {
+    eprintln!("{}", USAGE);
+    process::exit(2);
+}
+
+/// Reads a file as UTF-8 or Shift-JIS, whichever it is.
+fn read(path: &str) -> Result<(String, TextEncoding)> {
+    let bytes = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+    let encoding = match std::str::from_utf8(&bytes) {
+        Ok(_) => TextEncoding::Utf8,
+        Err(_) => TextEncoding::ShiftJis,
+    };
+    let text = decode(&bytes, Some(encoding))?.into_owned();
+    Ok((text, encoding))
+}
+
+/// Indexes the text of the file at `path`, naming the file in errors.
+fn parse(path: &str, text: &str) -> Result<Value> {
+    Ok(Value::parse(text).map_err(|err| format!("{}: {}", path, err))?)
+}
+
+fn inspect(path: &str) -> Result<bool> {
+    let (text, encoding) = read(path)?;
+    let document = Document::parse(&text)?;
+    let value = Value::parse(&text)?;
+    let get = |key| value.get(key).and_then(Value::as_str).unwrap_or("-");
+
+    println!("file:              {}", path);
+    println!("encoding:          {:?}", encoding);
+    let header: Vec<_> = document.header().collect();
+    println!(
+        "date:              {}",
+        header.get(1).copied().unwrap_or("-")
+    );
+    println!("file name:         {}", get("_.file_name"));
+    println!("converter version: {}", get("_.converter.version"));
+    println!("property version:  {}", get("_.property.version"));
+
+    let sections = value.as_section().into_iter().flatten();
+    for (name, section) in sections {
+        if let Some(length) = section.get("length").and_then(Value::as_str) {
+            println!("{:<19}{}", format!("{}:", name), length);
+        }
+    }
+    if let Some(play_control) = value.get("play_control") {
+        println!("play_control:");
+        for (key, v) in play_control.leaves() {
+            println!("    {} = {}", key, v);
+        }
+    }
+    Ok(true)
+}
+
+fn validate(paths: &[String]) -> Result<bool> {
+    let mut valid = true;
+    for path in paths {
+        let (text, _) = read(path)?;
+        let (_, diagnostics) = from_str_lenient::<A3da>(&text);
+        if diagnostics.is_empty() {
+            println!("{}: ok", path);
+        }
+        for diagnostic in &diagnostics {
+            println!("{}: {}", path, diagnostic);
+        }
+        valid &= diagnostics.is_empty();
+    }
+    Ok(valid)
+}
+
+/// Writes `value` in the style of `original`: its header, line endings and
+/// encoding.
+fn render(original: &str, encoding: TextEncoding, value: &Value) -> Result<Vec<u8>> {
+    let line_ending = if original.contains("\r\n") {
+        LineEnding::CrLf
+    } else {
+        LineEnding::Lf
+    };
+    let eol = if line_ending == LineEnding::CrLf {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let mut output = String::new();
+    for line in Document::parse(original)?.header() {
+        output.push('#');
+        output.push_str(line);
+        output.push_str(eol);
+    }
+    let options = SerializerOptions::new().line_ending(line_ending);
+    output.push_str(&to_string_with(value, &options)?);
+    Ok(encode(&output, encoding)?.into_owned())
+}
+
+/// Rewrites each file sorted by key, keeping its header, line endings and
+/// encoding. Floats are rounded in `%g` style only if `precision` is given.
+/// Files that do not parse cleanly are reported and not written, since
+/// rewriting them would drop lines.
+fn format(paths: &[String], precision: Option<usize>) -> Result<bool> {
+    let mut valid = true;
+    for path in paths {
+        let (text, encoding) = read(path)?;
+        let (mut value, diagnostics) = Value::parse_lenient(&text);
+        if !diagnostics.is_empty() {
+            for diagnostic in &diagnostics {
+                println!("{}: {}", path, diagnostic);
+            }
+            println!("{}: not formatted", path);
+            valid = false;
+            continue;
+        }
+        if let Some(precision) = precision {
+            value.format_floats(precision);
+        }
+
+        let output = render(&text, encoding, &value)?;
+        if encode(&text, encoding)? == output {
+            println!("{}: unchanged", path);
+        } else {
+            fs::write(path, output)?;
+            println!("{}: formatted", path);
+        }
+    }
+    Ok(valid)
+}
+
+/// Merges `theirs` into `ours` in place, taking the arguments git passes a
+/// merge driver as `%O %A %B`. Conflicts keep our side and are reported.
+/// All three files are read before `ours` is written, so one with duplicate
+/// keys or malformed lines fails the merge and leaves `ours` as it was.
+fn merge(base: &str, ours: &str, theirs: &str) -> Result<bool> {
+    let (text, encoding) = read(ours)?;
+    let (merged, conflicts) = serde_a3da::merge(
+        &parse(base, &read(base)?.0)?,
+        &parse(ours, &text)?,
+        &parse(theirs, &read(theirs)?.0)?,
+    );
+    fs::write(ours, render(&text, encoding, &merged)?)?;
+    for conflict in &conflicts {
+        eprintln!("conflict: {}", conflict);
+    }
+    Ok(conflicts.is_empty())
+}
+
+/// Succeeds only if the files are the same, like `diff(1)`.
+fn compare(old: &str, new: &str) -> Result<bool> {
+    let old = Value::parse(&read(old)?.0)?;
+    let new = Value::parse(&read(new)?.0)?;
+    let changes = diff(&old, &new);
+    for change in &changes {
+        println!("{}", change);
+    }
+    Ok(changes.is_empty())
+}
+
+/// Writes the camera of a VMD motion as a new A3DA file named after `out`.
+fn from_vmd(path: &str, out: &str) -> Result<bool> {
+    let bytes = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+    let mut a3da = serde_a3da::from_vmd(&bytes, &serde_a3da::VmdOptions::new())?;
+    a3da.metadata.file_name = Path::new(out)
+        .file_name()
+        .map_or_else(String::new, |x| x.to_string_lossy().into_owned());
+    let options = SerializerOptions::new()
+        .header(true)
+        .float_precision(Some(6));
+    fs::write(out, to_string_with(&a3da, &options)?)?;
+    Ok(true)
+}
+
+fn csv(path: &str, patterns: &[String]) -> Result<bool> {
+    let (text, _) = read(path)?;
+    let channels = match patterns {
+        [] => vec![KeyGlob::parse("**")?],
+        _ => patterns
+            .iter()
+            .map(|x| KeyGlob::parse(x))
+            .collect::<std::result::Result<_, _>>()?,
+    };
+    let csv = serde_a3da::to_csv(&parse(path, &text)?, &channels)?;
+    io::stdout().write_all(csv.as_bytes())?;
+    Ok(true)
+}
+
+fn to_vmd(path: &str, out: &str) -> Result<bool> {
+    let (text, _) = read(path)?;
+    let a3da: A3da = serde_a3da::from_str(&text)?;
+    fs::write(
+        out,
+        serde_a3da::to_vmd(&a3da, &serde_a3da::VmdOptions::new())?,
+    )?;
+    Ok(true)
+}
+
+#[cfg(feature = "json")]
+fn to_json(path: &str, numbers: bool) -> Result<bool> {
+    let (text, _) = read(path)?;
+    let options = serde_a3da::JsonOptions::new().numbers(numbers);
+    let json = serde_a3da::to_json_with(&text, &options)?;
+    println!("{}", serde_json::to_string_pretty(&json)?);
+    Ok(true)
+}
+
+#[cfg(feature = "json")]
+fn from_json(path: &str) -> Result<bool> {
+    let json = fs::read(path).map_err(|err| format!("{}: {}", path, err))?;
+    let text = serde_a3da::from_json(&serde_json::from_slice(&json)?)?;
+    io::stdout().write_all(text.as_bytes())?;
+    Ok(true)
+}
+
+#[cfg(feature = "gltf")]
+fn gltf(path: &str, out: &str) -> Result<bool> {
+    let (text, _) = read(path)?;
+    let a3da: A3da = serde_a3da::from_str(&text)?;
+    serde_a3da::export_gltf(&a3da, &serde_a3da::GltfOptions::new()).save(out)?;
+    Ok(true)
+}
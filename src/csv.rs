@@ -0,0 +1,135 @@
+//! Baking of curves to per-frame values for spreadsheets and plotting.
+
+use std::fmt::Write;
+
+use crate::de::from_value;
+use crate::error::DeserializeError;
+use crate::key::{KeyGlob, KeyPath};
+use crate::model::Curve;
+use crate::value::Value;
+
+/// Bakes every curve whose key matches one of `channels` to CSV.
+///
+/// A curve is any section with a `type` that reads as one, such as
+/// `camera_root.0.view_point.fov`; `**.trans.*` picks every translation axis.
+/// There is a row for every frame in `begin..begin + size` of `play_control`,
+/// since `size` is the length of the animation rather than its last frame.
+/// The first column is the frame, followed by one column per curve in key
+/// order. Curves that are not animated leave their cells empty.
+pub fn to_csv(value: &Value, channels: &[KeyGlob]) -> Result<String, DeserializeError> {
+    let number = |key: &str| {
+        value
+            .get(key)
+            .and_then(Value::as_str)
+            .ok_or_else(|| DeserializeError::Message(format!("Missing {}", key)))?
+            .trim()
+            .parse::<f32>()
+            .map_err(|_| DeserializeError::ExpectedNumber)
+    };
+    let (begin, size) = (number("play_control.begin")?, number("play_control.size")?);
+
+    let mut curves = Vec::new();
+    collect_curves(&KeyPath::new(), value, &mut curves);
+    curves.retain(|(key, _)| channels.iter().any(|x| x.matches(key)));
+    curves.sort_by(|a, b| a.0.cmp(&b.0));
+    if curves.is_empty() {
+        return Err(DeserializeError::Message(
+            "No curves match the channels".to_string(),
+        ));
+    }
+
+    let mut output = String::from("frame");
+    for (key, _) in &curves {
+        output.push(',');
+        output.push_str(&field(&key.to_string()));
+    }
+    output.push('\n');
+    for i in 0..size.max(0.0).ceil() as usize {
+        let frame = begin + i as f32;
+        write!(output, "{}", frame).unwrap();
+        for (_, curve) in &curves {
+            output.push(',');
+            if let Some(value) = curve.value_at(frame) {
+                write!(output, "{}", value).unwrap();
+            }
+        }
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+/// Every section below `value` that reads as a curve, without looking inside
+/// curves.
+fn collect_curves(key: &KeyPath, value: &Value, curves: &mut Vec<(KeyPath, Curve)>) {
+    let section = match value {
+        Value::Section(x) => x,
+        Value::Scalar(_) => return,
+    };
+    if section.contains_key("type") {
+        if let Ok(curve) = from_value::<Curve>(value.clone()) {
+            curves.push((key.clone(), curve));
+            return;
+        }
+    }
+    for (name, value) in section {
+        collect_curves(&key.join(name.as_str()), value, curves);
+    }
+}
+
+/// Quotes a CSV field if it needs it.
+fn field(text: &str) -> String {
+    match text.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", text.replace('"', "\"\"")),
+        false => text.to_string(),
+    }
+}
+
+#[test]
+fn bake_curves() {
+    let input = std::fs::read_to_string("assets/CAMPV001_BASE.a3da").unwrap();
+    let value = Value::parse(&input).unwrap();
+    let channels = [
+        KeyGlob::parse("**.roll").unwrap(),
+        KeyGlob::parse("camera_root.*.view_point.fov").unwrap(),
+        KeyGlob::parse("camera_root.*.trans.*").unwrap(),
+    ];
+    let csv = to_csv(&value, &channels).unwrap();
+    let lines: Vec<_> = csv.lines().collect();
+    assert_eq!(
+        lines[0],
+        "frame,camera_root.0.trans.x,camera_root.0.trans.y,camera_root.0.trans.z,\
+         camera_root.0.view_point.fov,camera_root.0.view_point.roll"
+    );
+    assert_eq!(lines.len(), 1 + 9301);
+
+    let roll: Curve =
+        from_value(value.get("camera_root.0.view_point.roll").unwrap().clone()).unwrap();
+    let row: Vec<_> = lines[1 + 100].split(',').collect();
+    assert_eq!(row[..4], ["100", "", "", ""]);
+    assert_eq!(row[4], "0.93616");
+    assert_eq!(
+        row[5].parse::<f32>().unwrap(),
+        roll.value_at(100.0).unwrap()
+    );
+
+    assert!(to_csv(&value, &[KeyGlob::parse("nothing").unwrap()]).is_err());
+    assert_eq!(field("a,\"b\""), "\"a,\"\"b\"\"\"");
+}
+
+#[test]
+fn bake_from_begin() {
+    let input = "a.key.0.data=(10,0)
+a.key.0.type=1
+a.key.1.data=(14,4)
+a.key.1.type=1
+a.key.length=2
+a.max=14
+a.type=2
+play_control.begin=10
+play_control.fps=60
+play_control.size=3
+";
+    let value = Value::parse(input).unwrap();
+    let csv = to_csv(&value, &[KeyGlob::parse("**").unwrap()]).unwrap();
+    assert_eq!(csv, "frame,a\n10,0\n11,1\n12,2\n");
+}
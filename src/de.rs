@@ -1,263 +1,1731 @@
-use serde::{de, Deserialize};
+use serde::de::{self, Deserialize, DeserializeOwned};
+use slab_tree::*;
 
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
 use std::io;
-use std::ops::{AddAssign, MulAssign, Neg};
+use std::str::FromStr;
 
-use crate::error::DeserializeError;
+use crate::encoding::{decode, TextEncoding};
+use crate::error::{DeserializeError, Diagnostic};
+use crate::key::parse_index;
+use crate::reader::Lines;
+use crate::value::Value;
 
-struct Deserializer<R: io::BufRead> {
-    source: R,
-    buf: String,
-    depth: usize,
+type Result<T> = ::std::result::Result<T, DeserializeError>;
+
+/// One segment of a dotted key. `value` is set on the segment that ends a
+/// `key=value` line, along with the line it was read from. `used` records
+/// whether the value was consumed by the type being deserialized.
+struct Entry<'de> {
+    name: &'de str,
+    value: Option<&'de str>,
+    line: usize,
+    used: Cell<bool>,
 }
 
-type Result<T> = ::std::result::Result<T, DeserializeError>;
+/// Deserializes A3DA text without copying it.
+///
+/// The input is indexed once into a tree of key segments, where every segment
+/// and value is a slice of the input. Strings are then handed to visitors with
+/// `visit_borrowed_str`, so `&'de str` and `Cow<'de, str>` fields borrow.
+///
+/// When `diagnostics` is set, problems that can be worked around are recorded
+/// there instead of failing: see `from_str_lenient`.
+struct Deserializer<'de> {
+    tree: Tree<Entry<'de>>,
+    diagnostics: Option<RefCell<Vec<Diagnostic>>>,
+}
 
-impl<R: io::BufRead> Deserializer<R> {
-    fn from_reader<R0: AsRef<[u8]>>(reader: R0) -> Deserializer<io::Cursor<R0>> {
-        Deserializer { source: io::Cursor::new(reader), buf: String::new(), depth: 0 }
+/// What to do when a key is set twice, or is set both as a value and as a
+/// section (`a.b=1` and `a.b.c=2`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DuplicateKeys {
+    /// Fail with the lines of both occurrences.
+    Error,
+    /// Keep the line that comes first in the file and ignore the other.
+    FirstWins,
+    /// Keep the line that comes last in the file and ignore the other.
+    LastWins,
+}
+
+/// Controls how lenient deserialization is with hand-edited files.
+#[derive(Debug, Clone)]
+pub struct DeserializerOptions {
+    duplicate_keys: DuplicateKeys,
+    encoding: Option<TextEncoding>,
+}
+
+impl DeserializerOptions {
+    pub fn new() -> Self {
+        Self::default()
     }
-    fn read_line(&mut self) -> io::Result<Option<&str>> {
-        self.buf.clear();
-        let read_bytes = self.source.read_line(&mut self.buf)?;
-        if read_bytes > 0 {
-            let str = self.buf.trim_end();
-            Ok( Some(str) )
-        } else {
-            Ok(None)
+
+    pub fn duplicate_keys(mut self, duplicate_keys: DuplicateKeys) -> Self {
+        self.duplicate_keys = duplicate_keys;
+        self
+    }
+
+    /// Encoding of the bytes read by `from_reader_with`. `None` detects it:
+    /// see `decode`.
+    pub fn encoding(mut self, encoding: Option<TextEncoding>) -> Self {
+        self.encoding = encoding;
+        self
+    }
+}
+
+impl Default for DeserializerOptions {
+    fn default() -> Self {
+        Self {
+            duplicate_keys: DuplicateKeys::Error,
+            encoding: None,
         }
     }
-    fn split_line(&self) -> Option<(&str, &str)> {
-        let mut splits = self.buf.split('=');
-        let lhs = splits.next()?;
-        let rhs = splits.next()?;
-        Some((lhs, rhs))
+}
+
+impl<'de> Deserializer<'de> {
+    fn new(input: &'de str, options: &DeserializerOptions) -> Result<Self> {
+        Self::index(input, options, None)
     }
-    fn split_lhs(&self) -> Option<std::str::Split<'_, char>> {
-        let (lhs, _) = self.split_line()?;
-        Some(lhs.split('.'))
+
+    fn lenient(input: &'de str, options: &DeserializerOptions) -> Self {
+        let diagnostics = Some(RefCell::new(Vec::new()));
+        Self::index(input, options, diagnostics).expect("lenient indexing records errors")
     }
 
-    // Parse a group of decimal digits as an unsigned integer of type T.
-    //
-    // This implementation is a bit too lenient, for example `001` is not
-    // allowed in JSON. Also the various arithmetic operations can overflow and
-    // panic or return bogus data. But it is good enough for example code!
-    fn parse_unsigned<T>(&mut self) -> Result<T>
-    where
-        T: AddAssign<T> + MulAssign<T> + From<u8>,
-    {
-        todo!()
+    fn index(
+        input: &'de str,
+        options: &DeserializerOptions,
+        diagnostics: Option<RefCell<Vec<Diagnostic>>>,
+    ) -> Result<Self> {
+        let root = Entry {
+            name: "",
+            value: None,
+            line: 0,
+            used: Cell::new(false),
+        };
+        let mut tree = TreeBuilder::new().with_root(root).build();
+        let root = tree.root_id().unwrap();
+        let mut children = HashMap::new();
+
+        'lines: for line in Lines::new(input) {
+            let (line, lhs, rhs) = match line {
+                Ok(x) => x,
+                Err(err) => {
+                    recover(diagnostics.as_ref(), err)?;
+                    continue;
+                }
+            };
+            let mut parent = root;
+            for name in lhs.split('.').map(str::trim) {
+                let entry = tree.get(parent).unwrap().data();
+                if entry.value.is_some() && parent != root {
+                    let key = path(&tree, parent);
+                    match options.duplicate_keys {
+                        DuplicateKeys::Error => {
+                            let err = DeserializeError::ConflictingKey {
+                                key,
+                                value_line: entry.line,
+                                section_line: line,
+                            };
+                            recover(diagnostics.as_ref(), err)?;
+                            continue 'lines;
+                        }
+                        DuplicateKeys::FirstWins => continue 'lines,
+                        DuplicateKeys::LastWins => {
+                            tree.get_mut(parent).unwrap().data().value = None
+                        }
+                    }
+                }
+                parent = *children.entry((parent, name)).or_insert_with(|| {
+                    let entry = Entry {
+                        name,
+                        value: None,
+                        line,
+                        used: Cell::new(false),
+                    };
+                    tree.get_mut(parent).unwrap().append(entry).node_id()
+                });
+            }
+
+            let node = tree.get(parent).unwrap();
+            let entry = node.data();
+            let conflict = if entry.value.is_some() {
+                Some(DeserializeError::DuplicateKey {
+                    key: path(&tree, parent),
+                    first: entry.line,
+                    second: line,
+                })
+            } else {
+                node.first_child()
+                    .map(|x| DeserializeError::ConflictingKey {
+                        key: path(&tree, parent),
+                        value_line: line,
+                        section_line: x.data().line,
+                    })
+            };
+            if let Some(err) = conflict {
+                match options.duplicate_keys {
+                    DuplicateKeys::Error => {
+                        recover(diagnostics.as_ref(), err)?;
+                        continue;
+                    }
+                    DuplicateKeys::FirstWins => continue,
+                    DuplicateKeys::LastWins => remove_children(&mut tree, &mut children, parent),
+                }
+            }
+            let mut node = tree.get_mut(parent).unwrap();
+            let entry = node.data();
+            entry.value = Some(rhs);
+            entry.line = line;
+        }
+        Ok(Self { tree, diagnostics })
     }
 
-    // Parse a possible minus sign followed by a group of decimal digits as a
-    // signed integer of type T.
-    fn parse_signed<T>(&mut self) -> Result<T>
-    where
-        T: Neg<Output = T> + AddAssign<T> + MulAssign<T> + From<i8>,
-    {
-        // Optional minus sign, delegate to `parse_unsigned`, negate if negative.
-        unimplemented!()
+    /// Indexes the scalars of `value` without formatting them as text. Lines
+    /// in errors count the scalars in key order, which is the line they would
+    /// be on in the output of `to_string`.
+    fn from_value(value: &'de Value) -> Self {
+        let root = Entry {
+            name: "",
+            value: None,
+            line: 0,
+            used: Cell::new(false),
+        };
+        let mut tree = TreeBuilder::new().with_root(root).build();
+        let root = tree.root_id().unwrap();
+        add_value(&mut tree, root, value, &mut 0);
+        Self {
+            tree,
+            diagnostics: None,
+        }
+    }
+
+    fn root(&self) -> Node<'_, 'de> {
+        Node {
+            tree: &self.tree,
+            id: self.tree.root_id().unwrap(),
+            diagnostics: self.diagnostics.as_ref(),
+        }
+    }
+
+    /// The indexed keys as a `Value`, keeping every value as written.
+    fn to_value(&self) -> Value {
+        tree_value(self.tree.root().unwrap())
+    }
+
+    /// Keys of the values that were never consumed, in file order.
+    fn unused(&self) -> Vec<String> {
+        let root = self.root();
+        root.get()
+            .traverse_pre_order()
+            .filter(|x| x.data().value.is_some() && !x.data().used.get())
+            .map(|x| path(root.tree, x.node_id()))
+            .collect()
+    }
+}
+
+fn add_value<'de>(tree: &mut Tree<Entry<'de>>, id: NodeId, value: &'de Value, line: &mut usize) {
+    match value {
+        Value::Scalar(x) => {
+            *line += 1;
+            let mut node = tree.get_mut(id).unwrap();
+            let entry = node.data();
+            entry.value = Some(x);
+            entry.line = *line;
+        }
+        Value::Section(x) => {
+            for (name, value) in x {
+                let entry = Entry {
+                    name,
+                    value: None,
+                    line: *line + 1,
+                    used: Cell::new(false),
+                };
+                let child = tree.get_mut(id).unwrap().append(entry).node_id();
+                add_value(tree, child, value, line);
+            }
+        }
+    }
+}
+
+fn tree_value(node: NodeRef<'_, Entry<'_>>) -> Value {
+    match node.data().value {
+        Some(x) => Value::Scalar(x.to_string()),
+        None => Value::Section(
+            node.children()
+                .map(|x| (x.data().name.to_string(), tree_value(x)))
+                .collect(),
+        ),
+    }
+}
+
+/// The full dotted key of the node `id`.
+fn path(tree: &Tree<Entry<'_>>, id: NodeId) -> String {
+    let node = tree.get(id).unwrap();
+    let mut segments: Vec<_> = node.ancestors().map(|x| x.data().name).collect();
+    segments.pop();
+    segments.reverse();
+    segments.push(node.data().name);
+    segments.join(".")
+}
+
+/// Records `err` when deserializing leniently, or fails with it otherwise.
+fn recover(diagnostics: Option<&RefCell<Vec<Diagnostic>>>, err: DeserializeError) -> Result<()> {
+    match diagnostics {
+        Some(x) => {
+            x.borrow_mut().push(err.into());
+            Ok(())
+        }
+        None => Err(err),
+    }
+}
+
+/// Drops the section below `id`, so a later value can replace it.
+fn remove_children<'de>(
+    tree: &mut Tree<Entry<'de>>,
+    children: &mut HashMap<(NodeId, &'de str), NodeId>,
+    id: NodeId,
+) {
+    let node = tree.get(id).unwrap();
+    for x in node.traverse_pre_order().skip(1) {
+        children.remove(&(x.parent().unwrap().node_id(), x.data().name));
+    }
+    let ids: Vec<_> = node.children().map(|x| x.node_id()).collect();
+    for x in ids {
+        tree.remove(x, RemoveBehavior::DropChildren);
+    }
+}
+
+pub fn from_str<'de, T>(input: &'de str) -> Result<T>
+where
+    T: Deserialize<'de>,
+{
+    from_str_with(input, &DeserializerOptions::default())
+}
+
+pub fn from_str_with<'de, T>(input: &'de str, options: &DeserializerOptions) -> Result<T>
+where
+    T: Deserialize<'de>,
+{
+    let deserializer = Deserializer::new(input, options)?;
+    T::deserialize(deserializer.root())
+}
+
+/// Like `from_str`, but also returns the keys of every value `T` did not
+/// consume, such as fields a newer game revision added, in file order.
+pub fn from_str_with_unknown<'de, T>(input: &'de str) -> Result<(T, Vec<String>)>
+where
+    T: Deserialize<'de>,
+{
+    let deserializer = Deserializer::new(input, &DeserializerOptions::default())?;
+    let value = T::deserialize(deserializer.root())?;
+    Ok((value, deserializer.unused()))
+}
+
+/// Like `from_str`, but carries on past problems that can be worked around,
+/// returning the value that could be read along with every problem found, in
+/// line order. The value is `None` if a problem could not be worked around.
+///
+/// - Malformed lines are skipped.
+/// - Of keys set twice, or set as both a value and a section, the first line
+///   is kept.
+/// - Numbers and booleans that do not parse are read as `0`.
+/// - Sequences are read from the elements present, whatever their `length`,
+///   and a missing `length` is reported.
+/// - Inline tuples with too many items are cut short, and ones with too few
+///   are padded with `0`.
+pub fn from_str_lenient<'de, T>(input: &'de str) -> (Option<T>, Vec<Diagnostic>)
+where
+    T: Deserialize<'de>,
+{
+    let deserializer = Deserializer::lenient(input, &DeserializerOptions::default());
+    let value = T::deserialize(deserializer.root());
+    let mut diagnostics = deserializer.diagnostics.unwrap_or_default().into_inner();
+    let value = match value {
+        Ok(x) => Some(x),
+        Err(err) => {
+            diagnostics.push(err.into());
+            None
+        }
+    };
+    diagnostics.sort_by_key(|x| x.line);
+    (value, diagnostics)
+}
+
+/// Indexes `input` as `Value::parse_with` does.
+pub(crate) fn parse_value(input: &str, options: &DeserializerOptions) -> Result<Value> {
+    Ok(Deserializer::new(input, options)?.to_value())
+}
+
+/// Indexes `input` as `Value::parse_lenient` does.
+pub(crate) fn parse_value_lenient(input: &str) -> (Value, Vec<Diagnostic>) {
+    let deserializer = Deserializer::lenient(input, &DeserializerOptions::default());
+    let value = deserializer.to_value();
+    let mut diagnostics = deserializer.diagnostics.unwrap_or_default().into_inner();
+    diagnostics.sort_by_key(|x| x.line);
+    (value, diagnostics)
+}
+
+/// Deserializes a single value, such as the value half of a line.
+pub(crate) fn from_scalar<'de, T>(value: &'de str) -> Result<T>
+where
+    T: Deserialize<'de>,
+{
+    T::deserialize(Scalar(value))
+}
+
+/// Deserializes `T` from a key tree built by `to_value` or by hand.
+pub fn from_value<T>(value: Value) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let deserializer = Deserializer::from_value(&value);
+    T::deserialize(deserializer.root())
+}
+
+/// Reads a whole file, decoding it as UTF-8 or Shift-JIS, whichever it is.
+pub fn from_reader<R, T>(reader: R) -> Result<T>
+where
+    R: io::Read,
+    T: DeserializeOwned,
+{
+    from_reader_with(reader, &DeserializerOptions::default())
+}
+
+pub fn from_reader_with<R, T>(mut reader: R, options: &DeserializerOptions) -> Result<T>
+where
+    R: io::Read,
+    T: DeserializeOwned,
+{
+    let mut input = Vec::new();
+    reader.read_to_end(&mut input)?;
+    let input = decode(&input, options.encoding)?;
+    from_str_with(&input, options)
+}
+
+/// A key in the indexed tree, deserialized as a section when it has children
+/// and as a scalar when it holds a value.
+#[derive(Clone, Copy)]
+struct Node<'a, 'de> {
+    tree: &'a Tree<Entry<'de>>,
+    id: NodeId,
+    diagnostics: Option<&'a RefCell<Vec<Diagnostic>>>,
+}
+
+impl<'a, 'de> Node<'a, 'de> {
+    fn get(&self) -> NodeRef<'a, Entry<'de>> {
+        self.tree.get(self.id).unwrap()
+    }
+
+    fn scalar(&self) -> Result<Scalar<'de>> {
+        let entry = self.get().data();
+        entry.used.set(true);
+        entry
+            .value
+            .map(Scalar)
+            .ok_or(DeserializeError::ExpectedValue)
+    }
+
+    /// The full dotted key of this node.
+    fn path(&self) -> String {
+        path(self.tree, self.id)
+    }
+
+    /// Records a problem with this node when deserializing leniently, or
+    /// fails with it otherwise.
+    fn recover(&self, err: DeserializeError) -> Result<()> {
+        recover(self.diagnostics, self.error(err))
+    }
+
+    /// The value of this node, or `0` if it does not `parse` and the problem
+    /// was recorded. Zero reads as a number or `false` alike.
+    fn lenient<T>(&self, parse: impl FnOnce(Scalar<'de>) -> Result<T>) -> Result<Scalar<'de>> {
+        let scalar = self.scalar()?;
+        if self.diagnostics.is_some() {
+            if let Err(err) = parse(scalar) {
+                self.recover(err)?;
+                return Ok(Scalar("0"));
+            }
+        }
+        Ok(scalar)
+    }
+
+    /// Attaches this node's key and line to an error raised while
+    /// deserializing it, unless a node below it already did.
+    fn error(&self, err: DeserializeError) -> DeserializeError {
+        match err {
+            DeserializeError::At { .. } | DeserializeError::MalformedLine(_) => err,
+            err => DeserializeError::At {
+                key: self.path(),
+                line: self.get().data().line,
+                source: Box::new(err),
+            },
+        }
+    }
+
+    fn children(&self) -> impl Iterator<Item = (Segment<'de>, Node<'a, 'de>)> + 'a {
+        let Node {
+            tree, diagnostics, ..
+        } = *self;
+        self.get().children().map(move |x| {
+            let node = Node {
+                tree,
+                id: x.node_id(),
+                diagnostics,
+            };
+            (Segment(x.data().name), node)
+        })
+    }
+
+    fn child(&self, name: &str) -> Option<Node<'a, 'de>> {
+        self.children().find(|(x, _)| x.0 == name).map(|(_, x)| x)
+    }
+
+    fn has_children(&self) -> bool {
+        self.get().first_child().is_some()
+    }
+
+    fn is_seq(&self) -> bool {
+        let mut length = false;
+        for (name, _) in self.children() {
+            match name.0 {
+                "length" => length = true,
+                x if x.bytes().all(|x| x.is_ascii_digit()) => {}
+                _ => return false,
+            }
+        }
+        length
+    }
+
+    /// Collects the `0`, `1`, ... children of an indexed section, checking them
+    /// against its `length` key.
+    fn elements(&self) -> Result<Vec<Node<'a, 'de>>> {
+        let mut elements = Vec::new();
+        for (name, node) in self.children() {
+            if let Some(index) = parse_index(name.0) {
+                elements.push((index, node));
+            }
+        }
+        elements.sort_by_key(|(index, _)| *index);
+        let gap = elements
+            .iter()
+            .enumerate()
+            .find(|(i, (index, _))| i != index);
+        if let Some((i, _)) = gap {
+            self.recover(DeserializeError::MissingIndex(i))?;
+        }
+        match self.child("length") {
+            Some(length) => {
+                let expected = match length.scalar()?.parse_integer::<usize>() {
+                    Ok(x) => Some(x),
+                    Err(err) => length.recover(err).map(|_| None)?,
+                };
+                if let Some(expected) = expected.filter(|x| *x != elements.len()) {
+                    self.recover(DeserializeError::LengthMismatch {
+                        expected,
+                        found: elements.len(),
+                    })?;
+                }
+            }
+            None if self.diagnostics.is_some() => {
+                self.recover(DeserializeError::MissingLength)?;
+            }
+            None => {}
+        }
+        Ok(elements.into_iter().map(|(_, x)| x).collect())
     }
 }
 
-impl<'de, 'a, R: io::BufRead> de::Deserializer<'de> for &'a mut Deserializer<R> {
+/// The value half of a `key=value` line, or one item of a `(…)` tuple.
+#[derive(Clone, Copy)]
+struct Scalar<'de>(&'de str);
+
+impl<'de> Scalar<'de> {
+    fn parse<T: FromStr>(self) -> Result<T> {
+        self.0.parse().map_err(|_| DeserializeError::ExpectedNumber)
+    }
+
+    fn parse_integer<T: FromStr>(self) -> Result<T> {
+        self.0
+            .parse()
+            .map_err(|_| DeserializeError::ExpectedInteger)
+    }
+
+    fn parse_bool(self) -> Result<bool> {
+        match self.0 {
+            "1" | "true" => Ok(true),
+            "0" | "false" => Ok(false),
+            _ => Err(DeserializeError::ExpectedBool),
+        }
+    }
+
+    /// Splits an inline `(a,b,c)` value into its items.
+    fn tuple(self) -> Result<impl Iterator<Item = Scalar<'de>>> {
+        let inner = self
+            .0
+            .strip_prefix('(')
+            .and_then(|x| x.strip_suffix(')'))
+            .ok_or(DeserializeError::ExpectedTuple)?;
+        let items = if inner.is_empty() { None } else { Some(inner) };
+        Ok(items
+            .into_iter()
+            .flat_map(|x| x.split(','))
+            .map(|x| Scalar(x.trim())))
+    }
+}
+
+macro_rules! deserialize_scalar {
+    ($($method:ident => $visit:ident($parse:ident),)*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> Result<V::Value>
+            where
+                V: de::Visitor<'de> {
+                visitor.$visit(self.$parse()?)
+            }
+        )*
+    };
+}
+
+macro_rules! forward_to_lenient_scalar {
+    ($($method:ident => $parse:ident::<$ty:ty>,)*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> Result<V::Value>
+            where
+                V: de::Visitor<'de> {
+                self.lenient(Scalar::$parse::<$ty>)?.$method(visitor)
+            }
+        )*
+    };
+}
+
+macro_rules! forward_to_scalar {
+    ($($method:ident,)*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> Result<V::Value>
+            where
+                V: de::Visitor<'de> {
+                self.scalar()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Scalar<'de> {
     type Error = DeserializeError;
 
+    /// Without type information, `(…)` is read as a tuple and anything that
+    /// parses as a number as one; everything else is a string.
     fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        todo!()
+        V: de::Visitor<'de>,
+    {
+        if self.0.starts_with('(') {
+            self.deserialize_seq(visitor)
+        } else if let Ok(v) = self.0.parse::<u64>() {
+            visitor.visit_u64(v)
+        } else if let Ok(v) = self.0.parse::<i64>() {
+            visitor.visit_i64(v)
+        } else if let Ok(v) = self.0.parse::<f64>() {
+            visitor.visit_f64(v)
+        } else {
+            visitor.visit_borrowed_str(self.0)
+        }
     }
 
     fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        todo!()
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_bool(self.parse_bool()?)
+    }
+
+    deserialize_scalar! {
+        deserialize_i8 => visit_i8(parse_integer),
+        deserialize_i16 => visit_i16(parse_integer),
+        deserialize_i32 => visit_i32(parse_integer),
+        deserialize_i64 => visit_i64(parse_integer),
+        deserialize_u8 => visit_u8(parse_integer),
+        deserialize_u16 => visit_u16(parse_integer),
+        deserialize_u32 => visit_u32(parse_integer),
+        deserialize_u64 => visit_u64(parse_integer),
+        deserialize_f32 => visit_f32(parse),
+        deserialize_f64 => visit_f64(parse),
     }
 
-    fn deserialize_i8<V>(mut self, visitor: V) -> Result<V::Value>
+    fn deserialize_char<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        visitor.visit_i8(self.parse_signed()?)
+        V: de::Visitor<'de>,
+    {
+        let mut chars = self.0.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => visitor.visit_char(c),
+            _ => Err(DeserializeError::ExpectedChar),
+        }
     }
 
-    fn deserialize_i16<V>(mut self, visitor: V) -> Result<V::Value>
+    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        visitor.visit_i16(self.parse_signed()?)
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_borrowed_str(self.0)
     }
 
-    fn deserialize_i32<V>(mut self, visitor: V) -> Result<V::Value>
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        visitor.visit_i32(self.parse_signed()?)
+        V: de::Visitor<'de>,
+    {
+        self.deserialize_str(visitor)
     }
 
-    fn deserialize_i64<V>(mut self, visitor: V) -> Result<V::Value>
+    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        visitor.visit_i64(self.parse_signed()?)
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_borrowed_bytes(self.0.as_bytes())
     }
 
-    fn deserialize_u8<V>(mut self, visitor: V) -> Result<V::Value>
+    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        visitor.visit_u8(self.parse_unsigned()?)
+        V: de::Visitor<'de>,
+    {
+        self.deserialize_bytes(visitor)
     }
 
-    fn deserialize_u16<V>(mut self, visitor: V) -> Result<V::Value>
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        visitor.visit_u16(self.parse_unsigned()?)
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_some(self)
     }
 
-    fn deserialize_u32<V>(mut self, visitor: V) -> Result<V::Value>
+    fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        visitor.visit_u32(self.parse_unsigned()?)
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_unit()
     }
 
-    fn deserialize_u64<V>(mut self, visitor: V) -> Result<V::Value>
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        visitor.visit_u64(self.parse_unsigned()?)
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_unit()
     }
 
-    fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        todo!()
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
     }
 
-    fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        todo!()
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_seq(de::value::SeqDeserializer::new(self.tuple()?))
     }
 
-    fn deserialize_char<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        todo!()
+        V: de::Visitor<'de>,
+    {
+        let items: Vec<_> = self.tuple()?.collect();
+        if items.len() != len {
+            return Err(DeserializeError::TupleArity {
+                expected: len,
+                found: items.len(),
+            });
+        }
+        visitor.visit_seq(de::value::SeqDeserializer::new(items.into_iter()))
     }
 
-    fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_tuple_struct<V>(
+        self,
+        _name: &'static str,
+        len: usize,
+        visitor: V,
+    ) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        visitor.visit_str(self.split_line().ok_or(DeserializeError::Eof)?.1)
+        V: de::Visitor<'de>,
+    {
+        self.deserialize_tuple(len, visitor)
     }
 
-    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_map<V>(self, _visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        todo!()
+        V: de::Visitor<'de>,
+    {
+        Err(DeserializeError::ExpectedSection)
     }
 
-    fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        todo!()
+        V: de::Visitor<'de>,
+    {
+        Err(DeserializeError::ExpectedSection)
     }
 
-    fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_enum(Enum {
+            variant: self,
+            node: None,
+        })
+    }
+
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        // Enum variants are written as their index.
+        match self.0.parse::<u64>() {
+            Ok(index) => visitor.visit_u64(index),
+            Err(_) => visitor.visit_borrowed_str(self.0),
+        }
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        todo!()
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+}
+
+impl<'de> de::IntoDeserializer<'de, DeserializeError> for Scalar<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+impl<'a, 'de> de::Deserializer<'de> for Node<'a, 'de> {
+    type Error = DeserializeError;
+
+    /// Sections are read as maps, except those with a `length` key whose other
+    /// keys are all indices, which are read as sequences.
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        if !self.has_children() {
+            return self.scalar()?.deserialize_any(visitor);
+        }
+        if self.is_seq() {
+            self.deserialize_seq(visitor)
+        } else {
+            self.deserialize_map(visitor)
+        }
+    }
+
+    fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        self.lenient(Scalar::parse_bool)?.deserialize_bool(visitor)
+    }
+
+    forward_to_lenient_scalar! {
+        deserialize_i8 => parse_integer::<i8>,
+        deserialize_i16 => parse_integer::<i16>,
+        deserialize_i32 => parse_integer::<i32>,
+        deserialize_i64 => parse_integer::<i64>,
+        deserialize_u8 => parse_integer::<u8>,
+        deserialize_u16 => parse_integer::<u16>,
+        deserialize_u32 => parse_integer::<u32>,
+        deserialize_u64 => parse_integer::<u64>,
+        deserialize_f32 => parse::<f32>,
+        deserialize_f64 => parse::<f64>,
+    }
+
+    forward_to_scalar! {
+        deserialize_char,
+        deserialize_str,
+        deserialize_string,
+        deserialize_bytes,
+        deserialize_byte_buf,
+        deserialize_identifier,
     }
 
     fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        todo!()
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_some(self)
     }
 
     fn deserialize_unit<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
+        V: de::Visitor<'de>,
+    {
+        self.get().data().used.set(true);
         visitor.visit_unit()
     }
 
-    fn deserialize_unit_struct<V>(
-        self,
-        name: &'static str,
-        visitor: V,
-    ) -> Result<V::Value>
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-            visitor.visit_unit()
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_unit()
     }
 
-    fn deserialize_newtype_struct<V>(
-        self,
-        name: &'static str,
-        visitor: V,
-    ) -> Result<V::Value>
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-            visitor.visit_newtype_struct(self)
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
     }
 
     fn deserialize_seq<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        todo!()
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_seq(Elements(self.elements()?.into_iter()))
     }
 
     fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        todo!()
+        V: de::Visitor<'de>,
+    {
+        // Tuples are either written inline as `(a,b)` or indexed like a seq.
+        if self.has_children() {
+            return self.deserialize_seq(visitor);
+        }
+        let scalar = self.scalar()?;
+        if let (Some(_), Ok(items)) = (self.diagnostics, scalar.tuple()) {
+            let mut items: Vec<_> = items.collect();
+            if items.len() != len {
+                self.recover(DeserializeError::TupleArity {
+                    expected: len,
+                    found: items.len(),
+                })?;
+                items.resize(len, Scalar("0"));
+                return visitor.visit_seq(de::value::SeqDeserializer::new(items.into_iter()));
+            }
+        }
+        scalar.deserialize_tuple(len, visitor)
     }
 
     fn deserialize_tuple_struct<V>(
         self,
-        name: &'static str,
+        _name: &'static str,
         len: usize,
         visitor: V,
     ) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        todo!()
+        V: de::Visitor<'de>,
+    {
+        self.deserialize_tuple(len, visitor)
     }
 
     fn deserialize_map<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        todo!()
+        V: de::Visitor<'de>,
+    {
+        if !self.has_children() {
+            return Err(DeserializeError::ExpectedSection);
+        }
+        visitor.visit_map(Section::new(self.children()))
     }
 
     fn deserialize_struct<V>(
         self,
-        name: &'static str,
-        fields: &'static [&'static str],
+        _name: &'static str,
+        _fields: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        todo!()
+        V: de::Visitor<'de>,
+    {
+        self.deserialize_map(visitor)
     }
 
     fn deserialize_enum<V>(
         self,
-        name: &'static str,
-        variants: &'static [&'static str],
+        _name: &'static str,
+        _variants: &'static [&'static str],
         visitor: V,
     ) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        todo!()
+        V: de::Visitor<'de>,
+    {
+        // Unit variants are a bare index, the others a `type` key next to
+        // their `value`, `data` or fields.
+        match self.child("type") {
+            Some(variant) => visitor.visit_enum(Enum {
+                variant: variant.scalar()?,
+                node: Some(self),
+            }),
+            None => visitor.visit_enum(Enum {
+                variant: self.scalar()?,
+                node: None,
+            }),
+        }
     }
 
-    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value>
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+}
+
+impl<'a, 'de> de::IntoDeserializer<'de, DeserializeError> for Node<'a, 'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// A key segment, handed to visitors as a borrowed map key or field name.
+struct Segment<'de>(&'de str);
+
+impl<'de> Segment<'de> {
+    fn scalar(&self) -> Result<Scalar<'de>> {
+        Ok(Scalar(self.0))
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Segment<'de> {
+    type Error = DeserializeError;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        todo!()
+        V: de::Visitor<'de>,
+    {
+        visitor.visit_borrowed_str(self.0)
     }
 
-    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value>
+    // Integer map keys are written as plain segments, e.g. `section.3.field`.
+    forward_to_scalar! {
+        deserialize_i8,
+        deserialize_i16,
+        deserialize_i32,
+        deserialize_i64,
+        deserialize_u8,
+        deserialize_u16,
+        deserialize_u32,
+        deserialize_u64,
+        deserialize_char,
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i128 u128 f32 f64 str string bytes byte_buf option unit
+        unit_struct newtype_struct seq tuple tuple_struct map struct enum
+        identifier ignored_any
+    }
+}
+
+impl<'de> de::IntoDeserializer<'de, DeserializeError> for Segment<'de> {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// Visits the keys of a section, attaching the key to errors raised by its
+/// values.
+struct Section<'a, 'de, I> {
+    children: I,
+    value: Option<Node<'a, 'de>>,
+}
+
+impl<'a, 'de, I> Section<'a, 'de, I> {
+    fn new(children: I) -> Self {
+        Self {
+            children,
+            value: None,
+        }
+    }
+}
+
+impl<'a, 'de, I> de::MapAccess<'de> for Section<'a, 'de, I>
+where
+    I: Iterator<Item = (Segment<'de>, Node<'a, 'de>)>,
+{
+    type Error = DeserializeError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> Result<Option<K::Value>>
+    where
+        K: de::DeserializeSeed<'de>,
+    {
+        match self.children.next() {
+            Some((name, node)) => {
+                self.value = Some(node);
+                seed.deserialize(name).map(Some).map_err(|e| node.error(e))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> Result<V::Value>
+    where
+        V: de::DeserializeSeed<'de>,
+    {
+        let node = self.value.take().ok_or(DeserializeError::ExpectedValue)?;
+        seed.deserialize(node).map_err(|e| node.error(e))
+    }
+}
+
+/// Visits the elements of an indexed section.
+struct Elements<I>(I);
+
+impl<'a, 'de, I> de::SeqAccess<'de> for Elements<I>
+where
+    I: ExactSizeIterator<Item = Node<'a, 'de>>,
+    'de: 'a,
+{
+    type Error = DeserializeError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> Result<Option<T::Value>>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        match self.0.next() {
+            Some(node) => seed.deserialize(node).map(Some).map_err(|e| node.error(e)),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+struct Enum<'a, 'de> {
+    variant: Scalar<'de>,
+    node: Option<Node<'a, 'de>>,
+}
+
+impl<'a, 'de> Enum<'a, 'de> {
+    fn field(&self, name: &str) -> Result<Node<'a, 'de>> {
+        self.node
+            .and_then(|x| x.child(name))
+            .ok_or(DeserializeError::ExpectedSection)
+    }
+}
+
+impl<'a, 'de> de::EnumAccess<'de> for Enum<'a, 'de> {
+    type Error = DeserializeError;
+    type Variant = Self;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self)>
+    where
+        V: de::DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(self.variant)?;
+        Ok((variant, self))
+    }
+}
+
+impl<'a, 'de> de::VariantAccess<'de> for Enum<'a, 'de> {
+    type Error = DeserializeError;
+
+    fn unit_variant(self) -> Result<()> {
+        Ok(())
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value>
+    where
+        T: de::DeserializeSeed<'de>,
+    {
+        seed.deserialize(self.field("value")?)
+    }
+
+    fn tuple_variant<V>(self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: de::Visitor<'de>,
+    {
+        de::Deserializer::deserialize_tuple(self.field("data")?, len, visitor)
+    }
+
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> Result<V::Value>
     where
-        V: de::Visitor<'de> {
-        todo!()
+        V: de::Visitor<'de>,
+    {
+        let node = self.node.ok_or(DeserializeError::ExpectedSection)?;
+        let fields = node.children().filter(|(name, _)| name.0 != "type");
+        visitor.visit_map(Section::new(fields))
+    }
+}
+
+#[test]
+fn deserialize_struct() {
+    use serde_derive::*;
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Test {
+        foo: u32,
+        bar: f32,
+        baz_array: Vec<u8>,
+    }
+
+    let input = "bar=39.39
+baz_array.0=39
+baz_array.1=39
+baz_array.2=69
+baz_array.3=0
+baz_array.length=4
+foo=69
+";
+    let test = Test {
+        foo: 69,
+        bar: 39.39,
+        baz_array: vec![39, 39, 69, 0],
+    };
+    assert_eq!(from_str::<Test>(input).unwrap(), test);
+}
+
+#[test]
+fn deserialize_borrowed() {
+    use serde_derive::*;
+    use std::borrow::Cow;
+    #[derive(Deserialize)]
+    struct A3daFile<'a> {
+        #[serde(rename = "_", borrow)]
+        metadata: A3daMetadata<'a>,
+    }
+
+    #[derive(Deserialize)]
+    struct A3daMetadata<'a> {
+        file_name: &'a str,
+        #[serde(borrow)]
+        converter: Cow<'a, str>,
+    }
+
+    let input = "#A3DA__________
+#Wed Dec 30 15:32:02 2009
+_.converter=20050823
+_.file_name=CAMPV001_BASE.a3da
+";
+    let a3da: A3daFile = from_str(input).unwrap();
+    assert_eq!(a3da.metadata.file_name, "CAMPV001_BASE.a3da");
+    let offset = input.find("CAMPV001").unwrap();
+    assert_eq!(a3da.metadata.file_name.as_ptr(), input[offset..].as_ptr());
+    assert!(matches!(a3da.metadata.converter, Cow::Borrowed("20050823")));
+}
+
+#[test]
+fn deserialize_keys() {
+    use serde_derive::*;
+    #[derive(Deserialize, PartialEq, Debug)]
+    enum Key {
+        Null,
+        Linear(f32, f32),
+        Hermite(f32, f32, f32),
+        HermiteTangents(f32, f32, f32, f32),
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Curve {
+        r#type: u8,
+        key: Vec<Key>,
+        max: u32,
+    }
+
+    let input = std::fs::read_to_string("assets/CAMPV001_BASE.a3da").unwrap();
+    #[derive(Deserialize)]
+    struct Root {
+        camera_root: Vec<CameraRoot>,
+    }
+    #[derive(Deserialize)]
+    struct CameraRoot {
+        view_point: ViewPoint,
+    }
+    #[derive(Deserialize)]
+    struct ViewPoint {
+        aspect: f32,
+        fov_is_horizontal: bool,
+        roll: Curve,
+    }
+
+    let root: Root = from_str(&input).unwrap();
+    let view_point = &root.camera_root[0].view_point;
+    assert_eq!(view_point.aspect, 1.77778);
+    assert!(view_point.fov_is_horizontal);
+    assert_eq!(view_point.roll.r#type, 3);
+    assert_eq!(view_point.roll.max, 9301);
+    assert_eq!(view_point.roll.key.len(), 48);
+    assert_eq!(view_point.roll.key[0], Key::Linear(0.0, 0.00448206));
+    assert_eq!(
+        view_point.roll.key[10],
+        Key::Hermite(1894.0, 0.00763023, -0.00272732)
+    );
+}
+
+#[test]
+fn deserialize_canonical_indices() {
+    use serde_derive::*;
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Root {
+        y: Vec<u32>,
+    }
+
+    let input = "y.0=1\ny.01=3\ny.1=2\ny.+1=4\ny.length=2\n";
+    let (root, unknown) = from_str_with_unknown::<Root>(input).unwrap();
+    assert_eq!(root.y, [1, 2]);
+    assert_eq!(unknown, ["y.01", "y.+1"]);
+}
+
+#[test]
+fn round_trip_enums() {
+    use serde_derive::*;
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    enum Curve {
+        Null,
+        Static(f32),
+        Linear(f32, f32),
+        Hermite { max: u32, key: Vec<(f32, f32)> },
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Test {
+        a: Curve,
+        b: Curve,
+        c: Curve,
+        d: Curve,
+    }
+
+    let test = Test {
+        a: Curve::Null,
+        b: Curve::Static(1.5),
+        c: Curve::Linear(2.0, 0.25),
+        d: Curve::Hermite {
+            max: 10,
+            key: vec![(0.0, 1.0), (10.0, 2.0)],
+        },
+    };
+    let output = crate::to_string(&test).unwrap();
+    assert_eq!(from_str::<Test>(&output).unwrap(), test);
+}
+
+#[test]
+fn deserialize_whitespace_and_equals() {
+    use serde_derive::*;
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Event<'a> {
+        name: &'a str,
+        param: &'a str,
+        data: (f32, f32),
+    }
+
+    let input = "  data = (0, 1.5)\r
+name=  event a
+param=path/to/file.a3da?x=1&y=2
+";
+    let event = Event {
+        name: "event a",
+        param: "path/to/file.a3da?x=1&y=2",
+        data: (0.0, 1.5),
+    };
+    assert_eq!(from_str::<Event>(input).unwrap(), event);
+    assert!(matches!(
+        from_str::<Event>("=1\n"),
+        Err(DeserializeError::MalformedLine(1))
+    ));
+}
+
+#[test]
+fn deserialize_maps() {
+    use serde_derive::*;
+    use std::collections::{BTreeMap, HashMap};
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Object {
+        frame: u32,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Test<'a> {
+        #[serde(borrow)]
+        object: HashMap<&'a str, Object>,
+        motion: BTreeMap<u32, String>,
+    }
+
+    let input = "motion.10=walk
+motion.2=idle
+object.STGPV001_BG.frame=3
+object.STGPV001_FLOOR.frame=7
+";
+    let test: Test = from_str(input).unwrap();
+    assert_eq!(test.object["STGPV001_BG"], Object { frame: 3 });
+    assert_eq!(test.object["STGPV001_FLOOR"], Object { frame: 7 });
+    assert_eq!(
+        test.motion.into_iter().collect::<Vec<_>>(),
+        [(2, "idle".to_string()), (10, "walk".to_string())]
+    );
+}
+
+#[test]
+fn deserialize_flatten_and_untagged() {
+    use serde_derive::*;
+    use std::collections::HashMap;
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Vec3 {
+        x: f32,
+        y: f32,
+        z: f32,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Transform {
+        trans: Vec3,
+        scale: Vec3,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    #[serde(untagged)]
+    enum Value {
+        List(Vec<u32>),
+        Tuple(f32, f32),
+        Number(f32),
+        Name(String),
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Object {
+        name: String,
+        #[serde(flatten)]
+        transform: Transform,
+        #[serde(flatten)]
+        rest: HashMap<String, Value>,
     }
+
+    let input = "a=(1,2.5)
+b=-0.5
+c.0=3
+c.1=4
+c.length=2
+d=stage
+name=STGPV001
+scale.x=1
+scale.y=1
+scale.z=1
+trans.x=0.25
+trans.y=-3
+trans.z=1e-005
+";
+    let object: Object = from_str(input).unwrap();
+    assert_eq!(object.name, "STGPV001");
+    assert_eq!(
+        object.transform.trans,
+        Vec3 {
+            x: 0.25,
+            y: -3.0,
+            z: 1e-5
+        }
+    );
+    assert_eq!(object.rest["a"], Value::Tuple(1.0, 2.5));
+    assert_eq!(object.rest["b"], Value::Number(-0.5));
+    assert_eq!(object.rest["c"], Value::List(vec![3, 4]));
+    assert_eq!(object.rest["d"], Value::Name("stage".to_string()));
+    assert_eq!(object.rest.len(), 4);
+
+    let ignored: serde::de::IgnoredAny = from_str(input).unwrap();
+    assert_eq!(ignored, serde::de::IgnoredAny);
+}
+
+#[test]
+fn deserialize_unknown_keys() {
+    use serde_derive::*;
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct PlayControl {
+        begin: u32,
+        size: u32,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    #[serde(deny_unknown_fields)]
+    struct Strict {
+        begin: u32,
+        size: u32,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Root<T> {
+        play_control: T,
+    }
+
+    let input = "play_control.begin=0
+play_control.div=2
+play_control.fps=60
+play_control.size=9301
+";
+    let (root, unknown) = from_str_with_unknown::<Root<PlayControl>>(input).unwrap();
+    assert_eq!(
+        root.play_control,
+        PlayControl {
+            begin: 0,
+            size: 9301
+        }
+    );
+    assert_eq!(unknown, ["play_control.div", "play_control.fps"]);
+
+    let err = from_str::<Root<Strict>>(input).unwrap_err();
+    assert!(
+        matches!(err, DeserializeError::At { ref key, line: 2, .. } if key == "play_control.div")
+    );
+    assert!(err.to_string().contains("unknown field `div`"));
+}
+
+#[test]
+fn deserialize_duplicate_keys() {
+    use std::collections::BTreeMap;
+    type Map = BTreeMap<String, BTreeMap<String, String>>;
+
+    let duplicate = "a.b=1\na.c=2\na.b=3\n";
+    let err = from_str::<Map>(duplicate).unwrap_err();
+    assert!(
+        matches!(err, DeserializeError::DuplicateKey { ref key, first: 1, second: 3 } if key == "a.b")
+    );
+
+    let leaf_first = "a.b=1\na.b.c=2\n";
+    let err = from_str::<Map>(leaf_first).unwrap_err();
+    assert!(matches!(
+        err,
+        DeserializeError::ConflictingKey { ref key, value_line: 1, section_line: 2 } if key == "a.b"
+    ));
+    let section_first = "a.b.c=2\na.b.d=3\na.b=1\n";
+    let err = from_str::<Map>(section_first).unwrap_err();
+    assert!(matches!(
+        err,
+        DeserializeError::ConflictingKey { ref key, value_line: 3, section_line: 1 } if key == "a.b"
+    ));
+
+    let err = from_str::<Map>("a . b =1\na.b=2\n").unwrap_err();
+    assert!(matches!(err, DeserializeError::DuplicateKey { ref key, .. } if key == "a.b"));
+    let err = from_str::<Map>("a.b.c=2\n a .b =1\n").unwrap_err();
+    assert!(matches!(err, DeserializeError::ConflictingKey { ref key, .. } if key == "a.b"));
+
+    let first = DeserializerOptions::new().duplicate_keys(DuplicateKeys::FirstWins);
+    let last = DeserializerOptions::new().duplicate_keys(DuplicateKeys::LastWins);
+    let value = |input, options| from_str_with::<Map>(input, options).unwrap()["a"]["b"].clone();
+    assert_eq!(value(duplicate, &first), "1");
+    assert_eq!(value(duplicate, &last), "3");
+    assert_eq!(value(leaf_first, &first), "1");
+    assert_eq!(value(section_first, &last), "1");
+
+    type Nested = BTreeMap<String, BTreeMap<String, BTreeMap<String, String>>>;
+    let nested = from_str_with::<Nested>(leaf_first, &last).unwrap();
+    assert_eq!(nested["a"]["b"]["c"], "2");
+    let nested = from_str_with::<Nested>("a.b.c=2\na.b=1\na.b.c=4\n", &last).unwrap();
+    assert_eq!(nested["a"]["b"]["c"], "4");
+}
+
+#[test]
+fn round_trip_values() {
+    use serde_derive::*;
+    #[derive(Serialize, Debug)]
+    struct Camera {
+        fov: f32,
+        trans: (f32, f32, f32),
+        frames: Vec<u32>,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Fov {
+        fov: f64,
+        frames: Vec<u32>,
+    }
+
+    let camera = Camera {
+        fov: 0.5,
+        trans: (1.0, 2.0, 3.0),
+        frames: vec![4, 5],
+    };
+    let mut value = crate::to_value(&camera).unwrap();
+    assert_eq!(value.get("trans.1").and_then(Value::as_str), Some("2"));
+    assert_eq!(
+        value.get("frames.length").and_then(Value::as_str),
+        Some("2")
+    );
+
+    value.insert("fov", "0.75");
+    value.insert("frames.2", "6");
+    value.insert("frames.length", "3");
+    let fov: Fov = from_value(value.clone()).unwrap();
+    assert_eq!(
+        fov,
+        Fov {
+            fov: 0.75,
+            frames: vec![4, 5, 6]
+        }
+    );
+    assert_eq!(
+        from_str::<Fov>(&crate::to_string(&value).unwrap()).unwrap(),
+        fov
+    );
+
+    value.insert("fov", "wide");
+    let err = from_value::<Fov>(value).unwrap_err();
+    assert!(matches!(err, DeserializeError::At { ref key, line: 1, .. } if key == "fov"));
+}
+
+#[test]
+fn deserialize_lenient() {
+    use serde_derive::*;
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Key {
+        data: (u32, f32, f32),
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Curve {
+        key: Vec<Key>,
+        max: u32,
+    }
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Root {
+        begin: u32,
+        fps: f32,
+        x: Curve,
+        y: Vec<u32>,
+    }
+
+    let input = "begin=zero
+fps=60
+fps=59.94
+not a pair
+x.key.0.data=(0,1,2,3)
+x.key.2.data=(2,1)
+x.key.length=3
+x.max=oops
+y.0=4
+";
+    let (root, diagnostics) = from_str_lenient::<Root>(input);
+    assert_eq!(
+        root.unwrap(),
+        Root {
+            begin: 0,
+            fps: 60.0,
+            x: Curve {
+                key: vec![
+                    Key {
+                        data: (0, 1.0, 2.0)
+                    },
+                    Key {
+                        data: (2, 1.0, 0.0)
+                    },
+                ],
+                max: 0,
+            },
+            y: vec![4],
+        }
+    );
+    let diagnostics: Vec<_> = diagnostics
+        .iter()
+        .map(|x| (x.key.as_str(), x.line, x.to_string()))
+        .collect();
+    assert_eq!(
+        diagnostics,
+        [
+            (
+                "begin",
+                1,
+                "begin (line 1): Expected an integer".to_string()
+            ),
+            (
+                "fps",
+                3,
+                "fps (line 3): Key fps is set on both line 2 and line 3".to_string()
+            ),
+            ("", 4, "Line 4 is not a key=value pair".to_string()),
+            ("x.key", 5, "x.key (line 5): Missing element 1".to_string()),
+            (
+                "x.key",
+                5,
+                "x.key (line 5): Expected 3 elements, found 2".to_string()
+            ),
+            (
+                "x.key.0.data",
+                5,
+                "x.key.0.data (line 5): Expected a tuple of 3 items, found 4".to_string()
+            ),
+            (
+                "x.key.2.data",
+                6,
+                "x.key.2.data (line 6): Expected a tuple of 3 items, found 2".to_string()
+            ),
+            (
+                "x.max",
+                8,
+                "x.max (line 8): Expected an integer".to_string()
+            ),
+            ("y", 9, "y (line 9): Missing length".to_string()),
+        ]
+    );
+
+    let (root, diagnostics) = from_str_lenient::<Root>("begin=1\n");
+    assert!(root.is_none());
+    assert_eq!(diagnostics[0].to_string(), "missing field `fps`");
+}
+
+#[test]
+fn deserialize_tuple_arity() {
+    use serde_derive::*;
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Pair(u32, f32);
+
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Root {
+        t: (u32, f32, f32),
+        p: Pair,
+    }
+
+    let root: Root = from_str("p=(1,2)\nt=(0,1,2)\n").unwrap();
+    assert_eq!(root.t, (0, 1.0, 2.0));
+    assert_eq!(root.p, Pair(1, 2.0));
+    for (input, len, found) in &[
+        ("p=(1,2)\nt=(0,1,2,3)\n", 3, 4),
+        ("p=(1,2)\nt=(0,1)\n", 3, 2),
+        ("p=(1,2,3)\nt=(0,1,2)\n", 2, 3),
+    ] {
+        let err = Diagnostic::from(from_str::<Root>(input).unwrap_err());
+        assert!(matches!(
+            err.error,
+            DeserializeError::TupleArity { expected, found: x } if expected == *len && x == *found
+        ));
+    }
+}
+
+#[test]
+fn deserialize_shift_jis() {
+    use serde_derive::*;
+    #[derive(Deserialize, PartialEq, Debug)]
+    struct Object {
+        name: String,
+    }
+
+    let bytes = b"#A3DA__________\nname=\x83X\x83e\x81[\x83W\n";
+    let object: Object = from_reader(&bytes[..]).unwrap();
+    assert_eq!(object.name, "ステージ");
+    let object: Object = from_reader("name=ステージ\n".as_bytes()).unwrap();
+    assert_eq!(object.name, "ステージ");
+
+    let utf8 = DeserializerOptions::new().encoding(Some(TextEncoding::Utf8));
+    let err = from_reader_with::<_, Object>(&bytes[..], &utf8).unwrap_err();
+    assert!(matches!(
+        err,
+        DeserializeError::InvalidEncoding(TextEncoding::Utf8)
+    ));
+
+    let mut reader = crate::Reader::new(&bytes[..]);
+    assert_eq!(reader.next().unwrap().unwrap().value.as_str(), "ステージ");
 }
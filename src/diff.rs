@@ -0,0 +1,303 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::de::from_value;
+use crate::key::KeyPath;
+use crate::model::Key;
+use crate::ser::format_float;
+use crate::value::Value;
+
+/// One difference found by `diff`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Added {
+        key: KeyPath,
+        value: String,
+    },
+    Removed {
+        key: KeyPath,
+        value: String,
+    },
+    Changed {
+        key: KeyPath,
+        old: String,
+        new: String,
+    },
+    /// A keyframe at a frame the old curve had no key at. `curve` is the path
+    /// of the curve's `key` sequence.
+    KeyAdded {
+        curve: KeyPath,
+        key: Key,
+    },
+    KeyRemoved {
+        curve: KeyPath,
+        key: Key,
+    },
+    /// A keyframe whose value, tangents or type changed at the same frame.
+    KeyChanged {
+        curve: KeyPath,
+        old: Key,
+        new: Key,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added { key, value } => write!(f, "+ {}={}", key, value),
+            Change::Removed { key, value } => write!(f, "- {}={}", key, value),
+            Change::Changed { key, old, new } => write!(f, "~ {}: {} -> {}", key, old, new),
+            Change::KeyAdded { curve, key } => {
+                write!(f, "+ {} @ {}: inserted", curve, key.frame())?;
+                match key.value() {
+                    Some(value) => write!(f, " with value {}", value),
+                    None => Ok(()),
+                }
+            }
+            Change::KeyRemoved { curve, key } => {
+                write!(f, "- {} @ {}: removed", curve, key.frame())
+            }
+            Change::KeyChanged { curve, old, new } => {
+                write!(f, "~ {} @ {}: ", curve, old.frame())?;
+                match (old.value(), new.value()) {
+                    (Some(a), Some(b)) if a != b => {
+                        let delta = f64::from(b) - f64::from(a);
+                        let sign = if delta > 0.0 { "+" } else { "" };
+                        write!(
+                            f,
+                            "value {} -> {} (\u{394} {}{})",
+                            a,
+                            b,
+                            sign,
+                            format_float(delta, 6)
+                        )
+                    }
+                    _ => write!(f, "{:?} -> {:?}", old, new),
+                }
+            }
+        }
+    }
+}
+
+/// Compares two key trees, such as two files read with `Value::parse`.
+///
+/// Values are compared by meaning rather than spelling, so `0.5` and `0.500`
+/// are equal. The `key` sequences of curves are matched up by frame rather
+/// than by index, so inserting a keyframe reports one added key instead of
+/// every later key shifting. Changes are listed in key order.
+pub fn diff(old: &Value, new: &Value) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_values(&KeyPath::new(), old, new, &mut changes);
+    changes
+}
+
+fn diff_values(key: &KeyPath, old: &Value, new: &Value, changes: &mut Vec<Change>) {
+    match (old, new) {
+        (Value::Scalar(a), Value::Scalar(b)) => {
+            if !same_scalar(a, b) {
+                changes.push(Change::Changed {
+                    key: key.clone(),
+                    old: a.clone(),
+                    new: b.clone(),
+                });
+            }
+        }
+        (Value::Section(a), Value::Section(b)) => diff_sections(key, a, b, changes),
+        _ => {
+            removed(key, old, changes);
+            added(key, new, changes);
+        }
+    }
+}
+
+fn diff_sections(
+    key: &KeyPath,
+    old: &BTreeMap<String, Value>,
+    new: &BTreeMap<String, Value>,
+    changes: &mut Vec<Change>,
+) {
+    let mut names: Vec<_> = old.keys().chain(new.keys()).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        let key = key.join(name.as_str());
+        match (old.get(name), new.get(name)) {
+            (Some(a), Some(b)) if name == "key" => match (keys(a), keys(b)) {
+                (Some(a), Some(b)) => diff_keys(&key, &a, &b, changes),
+                _ => diff_values(&key, a, b, changes),
+            },
+            (Some(a), Some(b)) => diff_values(&key, a, b, changes),
+            (Some(a), None) => removed(&key, a, changes),
+            (None, Some(b)) => added(&key, b, changes),
+            (None, None) => unreachable!(),
+        }
+    }
+}
+
+/// The keyframes of a curve's `key` sequence, ordered by frame.
+fn keys(value: &Value) -> Option<Vec<Key>> {
+    let mut keys: Vec<Key> = from_value(value.clone()).ok()?;
+    keys.sort_by(|a, b| a.frame().partial_cmp(&b.frame()).unwrap_or(Ordering::Equal));
+    Some(keys)
+}
+
+fn diff_keys(curve: &KeyPath, old: &[Key], new: &[Key], changes: &mut Vec<Change>) {
+    let (mut old, mut new) = (old.iter().peekable(), new.iter().peekable());
+    loop {
+        let order = match (old.peek(), new.peek()) {
+            (Some(a), Some(b)) => a.frame().partial_cmp(&b.frame()),
+            (Some(_), None) => Some(Ordering::Less),
+            (None, Some(_)) => Some(Ordering::Greater),
+            (None, None) => break,
+        };
+        let curve = curve.clone();
+        match order.unwrap_or(Ordering::Equal) {
+            Ordering::Less => changes.push(Change::KeyRemoved {
+                curve,
+                key: *old.next().unwrap(),
+            }),
+            Ordering::Greater => changes.push(Change::KeyAdded {
+                curve,
+                key: *new.next().unwrap(),
+            }),
+            Ordering::Equal => {
+                let (a, b) = (*old.next().unwrap(), *new.next().unwrap());
+                if a != b {
+                    changes.push(Change::KeyChanged {
+                        curve,
+                        old: a,
+                        new: b,
+                    });
+                }
+            }
+        }
+    }
+}
+
+fn added(key: &KeyPath, value: &Value, changes: &mut Vec<Change>) {
+    for (name, value) in value.leaves() {
+        changes.push(Change::Added {
+            key: join(key, &name),
+            value: value.to_string(),
+        });
+    }
+}
+
+fn removed(key: &KeyPath, value: &Value, changes: &mut Vec<Change>) {
+    for (name, value) in value.leaves() {
+        changes.push(Change::Removed {
+            key: join(key, &name),
+            value: value.to_string(),
+        });
+    }
+}
+
+/// `key` followed by the dotted `name` from `Value::leaves`, which is empty
+/// for the value at `key` itself.
+fn join(key: &KeyPath, name: &str) -> KeyPath {
+    let mut key = key.clone();
+    for segment in name.split('.').filter(|x| !x.is_empty()) {
+        key.push(segment);
+    }
+    key
+}
+
+/// Whether two scalars mean the same thing: the same text, the same number,
+/// or tuples whose items are all the same.
+fn same_scalar(a: &str, b: &str) -> bool {
+    match (tuple(a), tuple(b)) {
+        (Some(a), Some(b)) => {
+            let (a, b): (Vec<_>, Vec<_>) = (a.split(',').collect(), b.split(',').collect());
+            a.len() == b.len() && a.iter().zip(&b).all(|(a, b)| same_scalar(a, b))
+        }
+        _ => match (a.trim().parse::<f64>(), b.trim().parse::<f64>()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => a == b,
+        },
+    }
+}
+
+fn tuple(scalar: &str) -> Option<&str> {
+    scalar.strip_prefix('(')?.strip_suffix(')')
+}
+
+#[test]
+fn diff_curves() {
+    let old = Value::parse(
+        "a.fov.type=1
+a.fov.value=0.5
+a.name=x
+a.roll.key.0.data=(0,1)
+a.roll.key.0.type=1
+a.roll.key.1.data=(10,2)
+a.roll.key.1.type=1
+a.roll.key.2.data=(20,3)
+a.roll.key.2.type=1
+a.roll.key.length=3
+a.roll.max=20
+a.roll.type=2
+",
+    )
+    .unwrap();
+    let new = Value::parse(
+        "a.fov.type=1
+a.fov.value=0.500
+a.other.x=(1, 2)
+a.roll.key.0.data=(0,1.0)
+a.roll.key.0.type=1
+a.roll.key.1.data=(5,1.5)
+a.roll.key.1.type=1
+a.roll.key.2.data=(10,2.25)
+a.roll.key.2.type=1
+a.roll.key.length=3
+a.roll.max=30
+a.roll.type=2
+",
+    )
+    .unwrap();
+
+    let changes = diff(&old, &new);
+    let key = |x: &str| KeyPath::parse(x).unwrap();
+    let curve = key("a.roll.key");
+    assert_eq!(
+        changes,
+        [
+            Change::Removed {
+                key: key("a.name"),
+                value: "x".to_string()
+            },
+            Change::Added {
+                key: key("a.other.x"),
+                value: "(1, 2)".to_string()
+            },
+            Change::KeyAdded {
+                curve: curve.clone(),
+                key: Key::Linear(5.0, 1.5)
+            },
+            Change::KeyChanged {
+                curve: curve.clone(),
+                old: Key::Linear(10.0, 2.0),
+                new: Key::Linear(10.0, 2.25)
+            },
+            Change::KeyRemoved {
+                curve,
+                key: Key::Linear(20.0, 3.0)
+            },
+            Change::Changed {
+                key: key("a.roll.max"),
+                old: "20".to_string(),
+                new: "30".to_string()
+            },
+        ]
+    );
+    assert_eq!(
+        changes[3].to_string(),
+        "~ a.roll.key @ 10: value 2 -> 2.25 (\u{394} +0.25)"
+    );
+    assert_eq!(
+        changes[2].to_string(),
+        "+ a.roll.key @ 5: inserted with value 1.5"
+    );
+    assert!(diff(&old, &old).is_empty());
+}
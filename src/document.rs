@@ -0,0 +1,303 @@
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::str::FromStr;
+
+use crate::error::{DeserializeError, SerializeError};
+use crate::key::{KeyGlob, KeyPath};
+use crate::reader::split_line;
+use crate::ser::{check_key, check_value};
+
+/// An A3DA file kept line for line, for edits that should not disturb the
+/// rest of the file.
+///
+/// Every line is stored verbatim with its terminator, so header, comments,
+/// key order, whitespace and float spellings survive untouched. Writing a
+/// document back out reproduces the input byte for byte, except for the lines
+/// that were set, inserted or removed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Document {
+    lines: Vec<Line>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Line {
+    text: String,
+    entry: Option<KeyValue>,
+}
+
+/// The parsed half of a `key=value` line. `key` has whitespace around its
+/// segments removed, `value` is where the value sits in the line's text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct KeyValue {
+    key: String,
+    value: Range<usize>,
+}
+
+impl Line {
+    fn parse(text: String, line: usize) -> Result<Self, DeserializeError> {
+        let trimmed = text.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            return Ok(Self { text, entry: None });
+        }
+        let (lhs, rhs) = split_line(trimmed).ok_or(DeserializeError::MalformedLine(line))?;
+        let key = lhs.split('.').map(str::trim).collect::<Vec<_>>().join(".");
+        let start = rhs.as_ptr() as usize - text.as_ptr() as usize;
+        let value = start..start + rhs.len();
+        let entry = Some(KeyValue { key, value });
+        Ok(Self { text, entry })
+    }
+
+    fn key(&self) -> Option<&str> {
+        self.entry.as_ref().map(|x| &x.key[..])
+    }
+
+    fn value(&self) -> Option<&str> {
+        self.entry.as_ref().map(|x| &self.text[x.value.clone()])
+    }
+
+    fn has_eol(&self) -> bool {
+        self.text.ends_with('\n')
+    }
+}
+
+impl Document {
+    pub fn parse(input: &str) -> Result<Self, DeserializeError> {
+        let lines = input
+            .split_inclusive('\n')
+            .enumerate()
+            .map(|(i, x)| Line::parse(x.to_string(), i + 1))
+            .collect::<Result<_, _>>()?;
+        Ok(Self { lines })
+    }
+
+    /// The leading `#` lines, without the `#` and line terminator.
+    pub fn header(&self) -> impl Iterator<Item = &str> {
+        self.lines
+            .iter()
+            .take_while(|x| x.entry.is_none())
+            .map(|x| x.text.trim())
+            .filter_map(|x| x.strip_prefix('#'))
+    }
+
+    /// Every `key=value` pair, in file order.
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.lines
+            .iter()
+            .filter_map(|x| Some((x.key()?, x.value()?)))
+    }
+
+    /// Every line with its terminator, along with the key of `key=value`
+    /// lines and where their value sits in the line.
+    #[cfg(feature = "json")]
+    pub(crate) fn lines(&self) -> impl Iterator<Item = (&str, Option<(&str, Range<usize>)>)> {
+        self.lines.iter().map(|x| {
+            let entry = x.entry.as_ref().map(|x| (&x.key[..], x.value.clone()));
+            (&x.text[..], entry)
+        })
+    }
+
+    /// Every `key=value` pair whose key matches `glob`, in file order.
+    pub fn query<'a>(&'a self, glob: &'a KeyGlob) -> impl Iterator<Item = (KeyPath, &'a str)> {
+        self.entries().filter_map(move |(key, value)| {
+            let key = KeyPath::parse(key).ok()?;
+            Some((key, value)).filter(|(key, _)| glob.matches(key))
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.position(key).and_then(|x| self.lines[x].value())
+    }
+
+    pub fn contains_key(&self, key: &str) -> bool {
+        self.position(key).is_some()
+    }
+
+    /// Replaces the value of an existing key in place, keeping the rest of its
+    /// line as it was. Returns the previous value, or `None` without changing
+    /// anything if the key does not exist.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<Option<String>, SerializeError> {
+        check_value(value)?;
+        let line = match self.position(key) {
+            Some(x) => &mut self.lines[x],
+            None => return Ok(None),
+        };
+        let range = line.entry.as_ref().unwrap().value.clone();
+        let old = line.text[range.clone()].to_string();
+        line.text.replace_range(range.clone(), value);
+        line.entry.as_mut().unwrap().value = range.start..range.start + value.len();
+        Ok(Some(old))
+    }
+
+    /// Sets `key`, adding a line for it if it does not exist yet. New lines
+    /// go where they sort among the existing keys and use the line terminator
+    /// of the first line. Adding a key that would be both a value and a
+    /// section, such as `a.b` next to `a.b.c`, fails.
+    pub fn insert(&mut self, key: &str, value: &str) -> Result<Option<String>, SerializeError> {
+        check_key(key)?;
+        if self.contains_key(key) {
+            return self.set(key, value);
+        }
+        check_value(value)?;
+        let below = |a: &str, b: &str| a.starts_with(b) && a[b.len()..].starts_with('.');
+        let conflict = self
+            .lines
+            .iter()
+            .filter_map(Line::key)
+            .find(|x| below(x, key) || below(key, x));
+        if let Some(existing) = conflict {
+            return Err(SerializeError::ConflictingKey {
+                key: key.to_string(),
+                existing: existing.to_string(),
+            });
+        }
+
+        let index = self
+            .lines
+            .iter()
+            .position(|x| matches!(x.key(), Some(x) if x > key))
+            .unwrap_or_else(|| {
+                self.lines
+                    .iter()
+                    .rposition(|x| x.entry.is_some())
+                    .map_or(self.lines.len(), |x| x + 1)
+            });
+        let eol = self.line_ending();
+        if index == self.lines.len() {
+            if let Some(last) = self.lines.last_mut().filter(|x| !x.has_eol()) {
+                last.text.push_str(eol);
+            }
+        }
+        let text = format!("{}={}{}", key, value, eol);
+        let value = key.len() + 1..text.len() - eol.len();
+        let entry = Some(KeyValue {
+            key: key.to_string(),
+            value,
+        });
+        self.lines.insert(index, Line { text, entry });
+        Ok(None)
+    }
+
+    /// Removes the line of `key`, returning its value.
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let index = self.position(key)?;
+        let line = self.lines.remove(index);
+        line.value().map(str::to_string)
+    }
+
+    /// Removes every key below `section`, returning how many lines were
+    /// removed.
+    pub fn remove_section(&mut self, section: &str) -> usize {
+        let len = self.lines.len();
+        self.lines.retain(|x| match x.key() {
+            Some(key) => !(key.starts_with(section) && key[section.len()..].starts_with('.')),
+            None => true,
+        });
+        len - self.lines.len()
+    }
+
+    pub fn write_to<W: io::Write>(&self, mut writer: W) -> io::Result<()> {
+        for line in &self.lines {
+            writer.write_all(line.text.as_bytes())?;
+        }
+        writer.flush()
+    }
+
+    fn position(&self, key: &str) -> Option<usize> {
+        self.lines.iter().position(|x| x.key() == Some(key))
+    }
+
+    fn line_ending(&self) -> &'static str {
+        match self.lines.first() {
+            Some(x) if x.text.ends_with("\r\n") => "\r\n",
+            _ => "\n",
+        }
+    }
+}
+
+impl FromStr for Document {
+    type Err = DeserializeError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Self::parse(input)
+    }
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            f.write_str(&line.text)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn document_round_trip() {
+    let input = std::fs::read_to_string("assets/CAMPV001_BASE.a3da").unwrap();
+    let document = Document::parse(&input).unwrap();
+    assert_eq!(document.to_string(), input);
+    assert_eq!(
+        document.header().collect::<Vec<_>>(),
+        ["A3DA__________", "Wed Dec 30 15:32:02 2009"]
+    );
+    assert_eq!(
+        document.get("camera_root.0.interest.trans.x.key.1.data"),
+        Some("(738,-0.522281,3.31402e-006)")
+    );
+
+    let glob = KeyGlob::parse("camera_root.*.view_point.fov.*").unwrap();
+    let fov: Vec<_> = document.query(&glob).map(|(_, x)| x).collect();
+    assert_eq!(fov, ["1", "0.93616"]);
+    let glob = KeyGlob::parse("**.visibility.value").unwrap();
+    let visibility: Vec<_> = document.query(&glob).map(|(x, _)| x.to_string()).collect();
+    assert_eq!(
+        visibility,
+        [
+            "camera_root.0.interest.visibility.value",
+            "camera_root.0.view_point.visibility.value",
+            "camera_root.0.visibility.value"
+        ]
+    );
+}
+
+#[test]
+fn document_edits() {
+    let input = "#A3DA__________\r
+# hand tuned\r
+play_control.begin = 0\r
+play_control.fps=60.000\r
+play_control.size=9301";
+    let mut document = Document::parse(input).unwrap();
+
+    assert_eq!(
+        document.set("play_control.begin", "5").unwrap(),
+        Some("0".to_string())
+    );
+    assert_eq!(document.set("play_control.end", "5").unwrap(), None);
+    assert_eq!(document.insert("play_control.div", "2").unwrap(), None);
+    assert_eq!(document.insert("play_control.to", "1").unwrap(), None);
+    assert_eq!(
+        document.remove("play_control.size"),
+        Some("9301".to_string())
+    );
+    let output = "#A3DA__________\r
+# hand tuned\r
+play_control.begin = 5\r
+play_control.div=2\r
+play_control.fps=60.000\r
+play_control.to=1\r
+";
+    assert_eq!(document.to_string(), output);
+    assert_eq!(document.get("play_control.begin"), Some("5"));
+
+    assert!(document.set("play_control.fps", "6\n0").is_err());
+    assert!(document.insert("#play_control", "1").is_err());
+    for key in &["play_control", "play_control.div.x"] {
+        let err = document.insert(key, "1").unwrap_err();
+        assert!(matches!(err, SerializeError::ConflictingKey { .. }));
+    }
+    assert_eq!(document.remove_section("play"), 0);
+    assert_eq!(document.remove_section("play_control"), 4);
+    assert_eq!(document.to_string(), "#A3DA__________\r\n# hand tuned\r\n");
+}
@@ -0,0 +1,71 @@
+use encoding_rs::SHIFT_JIS;
+
+use std::borrow::Cow;
+use std::str;
+
+use crate::error::{DeserializeError, SerializeError};
+
+/// Character encoding of A3DA text.
+///
+/// Files from the original tools are Shift-JIS, which only matters for
+/// Japanese object, motion and file names; everything else is ASCII.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextEncoding {
+    Utf8,
+    ShiftJis,
+}
+
+/// Decodes A3DA text. With `None`, input that is valid UTF-8 is read as
+/// UTF-8 and anything else as Shift-JIS. A UTF-8 byte order mark is dropped.
+pub fn decode(
+    bytes: &[u8],
+    encoding: Option<TextEncoding>,
+) -> Result<Cow<'_, str>, DeserializeError> {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    match encoding {
+        Some(TextEncoding::Utf8) => str::from_utf8(bytes)
+            .map(Cow::Borrowed)
+            .map_err(|_| DeserializeError::InvalidEncoding(TextEncoding::Utf8)),
+        Some(TextEncoding::ShiftJis) => SHIFT_JIS
+            .decode_without_bom_handling_and_without_replacement(bytes)
+            .ok_or(DeserializeError::InvalidEncoding(TextEncoding::ShiftJis)),
+        None => match str::from_utf8(bytes) {
+            Ok(text) => Ok(Cow::Borrowed(text)),
+            Err(_) => decode(bytes, Some(TextEncoding::ShiftJis)),
+        },
+    }
+}
+
+/// Encodes A3DA text, failing on characters Shift-JIS has no code for.
+pub fn encode(text: &str, encoding: TextEncoding) -> Result<Cow<'_, [u8]>, SerializeError> {
+    match encoding {
+        TextEncoding::Utf8 => Ok(Cow::Borrowed(text.as_bytes())),
+        TextEncoding::ShiftJis => match SHIFT_JIS.encode(text) {
+            (_, _, true) => {
+                let c = text
+                    .chars()
+                    .find(|c| SHIFT_JIS.encode(c.encode_utf8(&mut [0; 4])).2)
+                    .unwrap_or(char::REPLACEMENT_CHARACTER);
+                Err(SerializeError::Unencodable(c, encoding))
+            }
+            (bytes, _, false) => Ok(bytes),
+        },
+    }
+}
+
+#[test]
+fn shift_jis() {
+    let text = "object.0.name=ステージ\n";
+    let bytes = encode(text, TextEncoding::ShiftJis).unwrap();
+    assert_eq!(&bytes[14..16], b"\x83\x58");
+    assert_eq!(decode(&bytes, None).unwrap(), text);
+    assert_eq!(decode(&bytes, Some(TextEncoding::ShiftJis)).unwrap(), text);
+    assert!(decode(&bytes, Some(TextEncoding::Utf8)).is_err());
+    assert_eq!(decode(text.as_bytes(), None).unwrap(), text);
+    assert_eq!(decode(b"\xEF\xBB\xBFa=1", None).unwrap(), "a=1");
+
+    assert!(matches!(
+        encode("name=😀", TextEncoding::ShiftJis),
+        Err(SerializeError::Unencodable('😀', TextEncoding::ShiftJis))
+    ));
+}
@@ -1,16 +1,29 @@
 use thiserror::*;
 
-use std;
-use std::fmt::Display;
+use std::fmt::{self, Display};
 
 use serde::{de, ser};
 
+use crate::encoding::TextEncoding;
+
 #[derive(Debug, Error)]
 pub enum SerializeError {
     #[error("{0}")]
     Message(String),
     #[error(transparent)]
     Io(#[from] std::io::Error),
+    #[error("Values must be nested under a key")]
+    MissingKey,
+    #[error("Key {0:?} cannot be written as an A3DA key")]
+    InvalidKey(String),
+    #[error("Value {0:?} cannot be written as an A3DA value")]
+    InvalidValue(String),
+    #[error("Map key {0:?} must be a non-empty key segment without '.' or '='")]
+    InvalidMapKey(String),
+    #[error("Key {key} cannot be added next to {existing}, since one is a section of the other")]
+    ConflictingKey { key: String, existing: String },
+    #[error("{0:?} cannot be encoded as {1:?}")]
+    Unencodable(char, TextEncoding),
 }
 
 #[derive(Debug, Error)]
@@ -21,8 +34,109 @@ pub enum DeserializeError {
     Io(#[from] std::io::Error),
     #[error("Reached the end of the file")]
     Eof,
+    #[error("Input is not valid {0:?}")]
+    InvalidEncoding(TextEncoding),
     #[error("Expected an integer")]
     ExpectedInteger,
+    #[error("Expected a number")]
+    ExpectedNumber,
+    #[error("Expected a boolean")]
+    ExpectedBool,
+    #[error("Expected a single character")]
+    ExpectedChar,
+    #[error("Expected a tuple")]
+    ExpectedTuple,
+    #[error("Expected a value")]
+    ExpectedValue,
+    #[error("Expected a section of keys")]
+    ExpectedSection,
+    #[error("Line {0} is not a key=value pair")]
+    MalformedLine(usize),
+    #[error("Key {0:?} has an empty segment")]
+    InvalidKey(String),
+    #[error("Missing element {0}")]
+    MissingIndex(usize),
+    #[error("Missing length")]
+    MissingLength,
+    #[error("Expected a tuple of {expected} items, found {found}")]
+    TupleArity { expected: usize, found: usize },
+    #[error("Expected {expected} elements, found {found}")]
+    LengthMismatch { expected: usize, found: usize },
+    #[error("Key {key} is set on both line {first} and line {second}")]
+    DuplicateKey {
+        key: String,
+        first: usize,
+        second: usize,
+    },
+    #[error("Key {key} is a value on line {value_line} but a section on line {section_line}")]
+    ConflictingKey {
+        key: String,
+        value_line: usize,
+        section_line: usize,
+    },
+    #[error("{key} (line {line}): {source}")]
+    At {
+        key: String,
+        line: usize,
+        source: Box<DeserializeError>,
+    },
+}
+
+/// A problem found by `from_str_lenient`, which carries on past it.
+#[derive(Debug)]
+pub struct Diagnostic {
+    /// The key the problem was found at, empty for lines without a key.
+    pub key: String,
+    pub line: usize,
+    pub error: DeserializeError,
+}
+
+impl From<DeserializeError> for Diagnostic {
+    fn from(error: DeserializeError) -> Self {
+        match error {
+            DeserializeError::At { key, line, source } => Self {
+                key,
+                line,
+                error: *source,
+            },
+            DeserializeError::MalformedLine(line) => Self {
+                key: String::new(),
+                line,
+                error,
+            },
+            DeserializeError::DuplicateKey {
+                ref key, second, ..
+            } => Self {
+                key: key.clone(),
+                line: second,
+                error,
+            },
+            DeserializeError::ConflictingKey {
+                ref key,
+                value_line,
+                section_line,
+            } => Self {
+                key: key.clone(),
+                line: value_line.max(section_line),
+                error,
+            },
+            error => Self {
+                key: String::new(),
+                line: 0,
+                error,
+            },
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.key.is_empty() {
+            write!(f, "{}", self.error)
+        } else {
+            write!(f, "{} (line {}): {}", self.key, self.line, self.error)
+        }
+    }
 }
 
 impl ser::Error for SerializeError {
@@ -0,0 +1,986 @@
+//! glTF 2.0 export for previewing A3DA animation in Blender or three.js.
+//!
+//! Cameras become glTF camera nodes. Their eye, interest and roll are baked
+//! into a translation and rotation per frame, since glTF cameras can only look
+//! down their own -Z axis. Times are frames divided by `play_control.fps`.
+//!
+//! Objects and the bones of `objhrc` entries become nodes, parented by name.
+//! Their curves keep their keys: Hermite and linear curves become
+//! `CUBICSPLINE` samplers with the tangents converted to per second, and hold
+//! curves `STEP` samplers. Euler rotations are converted to quaternions with
+//! their tangents at each key. Visibility uses `KHR_node_visibility`.
+
+use serde_json::{json, Map, Value as Json};
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+use crate::error::SerializeError;
+use crate::math::{axis_angle, camera_pose, euler, mul, sample, yfov, Quat};
+use crate::model::{A3da, CameraRoot, Curve, Key, Vec3};
+
+/// Controls what `export_gltf` writes.
+#[derive(Debug, Clone)]
+pub struct GltfOptions {
+    animation_pointer: bool,
+}
+
+impl GltfOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether an animated FOV or visibility is written as a
+    /// `KHR_animation_pointer` channel. Viewers without the extension ignore
+    /// it; without it, cameras and nodes keep their state at the first frame.
+    pub fn animation_pointer(mut self, animation_pointer: bool) -> Self {
+        self.animation_pointer = animation_pointer;
+        self
+    }
+}
+
+impl Default for GltfOptions {
+    fn default() -> Self {
+        Self {
+            animation_pointer: true,
+        }
+    }
+}
+
+/// A glTF asset and its single binary buffer.
+#[derive(Debug, Clone)]
+pub struct Gltf {
+    json: Json,
+    buffer: Vec<u8>,
+}
+
+impl Gltf {
+    /// The glTF JSON, whose only buffer has no `uri`.
+    pub fn json(&self) -> &Json {
+        &self.json
+    }
+
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// A `.gltf` file with the buffer embedded as a base64 data URI.
+    pub fn to_gltf(&self) -> String {
+        let mut json = self.json.clone();
+        json["buffers"][0]["uri"] = Json::from(format!(
+            "data:application/octet-stream;base64,{}",
+            base64(&self.buffer)
+        ));
+        json.to_string()
+    }
+
+    /// A binary `.glb` file.
+    pub fn to_glb(&self) -> Vec<u8> {
+        let mut json = self.json.to_string().into_bytes();
+        pad(&mut json, b' ');
+        let mut buffer = self.buffer.clone();
+        pad(&mut buffer, 0);
+
+        let length = 12 + 8 + json.len() + 8 + buffer.len();
+        let mut glb = Vec::with_capacity(length);
+        glb.extend_from_slice(b"glTF");
+        glb.extend_from_slice(&2u32.to_le_bytes());
+        glb.extend_from_slice(&(length as u32).to_le_bytes());
+        glb.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"JSON");
+        glb.extend_from_slice(&json);
+        glb.extend_from_slice(&(buffer.len() as u32).to_le_bytes());
+        glb.extend_from_slice(b"BIN\0");
+        glb.extend_from_slice(&buffer);
+        glb
+    }
+
+    /// Writes a `.glb` file if `path` ends in `.glb`, and a `.gltf` file
+    /// otherwise.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SerializeError> {
+        let path = path.as_ref();
+        let glb = path
+            .extension()
+            .is_some_and(|x| x.eq_ignore_ascii_case("glb"));
+        match glb {
+            true => fs::write(path, self.to_glb())?,
+            false => fs::write(path, self.to_gltf())?,
+        }
+        Ok(())
+    }
+}
+
+/// Converts the cameras, objects and object hierarchies of `a3da` to a glTF
+/// scene with one animation.
+///
+/// Object and bone curves keep their keys: Hermite and linear curves become
+/// `CUBICSPLINE` samplers, with linear keys given the slope of their segment
+/// as tangents, and hold curves become `STEP` samplers.
+///
+/// Rotations are Euler angles, which glTF cannot store, so each key becomes a
+/// quaternion with the tangent the angles give it there. The cubic between
+/// two quaternions only approximates the turn of the angles, so segments
+/// where an axis turns more than an eighth of a turn get extra keys to stay
+/// close.
+///
+/// A translation, rotation or scale mixing hold axes with smooth ones cannot
+/// be written with one sampler, since it interpolates all axes the same way.
+/// Those are baked to one `LINEAR` key per frame instead.
+pub fn export_gltf(a3da: &A3da, options: &GltfOptions) -> Gltf {
+    let mut builder = Builder::default();
+    let play_control = &a3da.play_control;
+    let timing = Timing {
+        begin: play_control.begin,
+        fps: play_control.fps,
+    };
+
+    if !a3da.camera_root.is_empty() {
+        let count = (play_control.size.ceil() as usize).max(1);
+        let frames: Vec<_> = (0..count).map(|i| timing.begin + i as f32).collect();
+        let input = builder.accessor(&timing.times(&frames), "SCALAR");
+        for (i, camera) in a3da.camera_root.iter().enumerate() {
+            export_camera(&mut builder, i, camera, &frames, input, options);
+        }
+    }
+
+    let mut names = HashMap::new();
+    let mut parent_names = Vec::new();
+    for object in &a3da.object {
+        let mut extras = json!({ "uid_name": object.uid_name });
+        if let Some(parent_name) = &object.parent_name {
+            extras["parent_name"] = json!(parent_name);
+        }
+        let transform = Transform {
+            rot: &object.rot,
+            scale: &object.scale,
+            trans: &object.trans,
+            visibility: &object.visibility,
+        };
+        let node = export_node(
+            &mut builder,
+            &object.name,
+            extras,
+            transform,
+            &timing,
+            options,
+        );
+        names.entry(object.name.as_str()).or_insert(node);
+        if let Some(parent_name) = &object.parent_name {
+            parent_names.push((node, parent_name.as_str()));
+        }
+    }
+    for hrc in &a3da.objhrc {
+        let mut extras = json!({ "uid_name": hrc.uid_name });
+        if let Some(parent_name) = &hrc.parent_name {
+            extras["parent_name"] = json!(parent_name);
+            parent_names.push((builder.nodes.len(), parent_name.as_str()));
+        }
+        let root = builder.node(json!({ "name": hrc.name, "extras": extras }));
+        names.entry(hrc.name.as_str()).or_insert(root);
+
+        let first = builder.nodes.len();
+        for bone in &hrc.node {
+            let transform = Transform {
+                rot: &bone.rot,
+                scale: &bone.scale,
+                trans: &bone.trans,
+                visibility: &bone.visibility,
+            };
+            let extras = json!({ "parent": bone.parent });
+            let node = export_node(
+                &mut builder,
+                &bone.name,
+                extras,
+                transform,
+                &timing,
+                options,
+            );
+            names.entry(bone.name.as_str()).or_insert(node);
+        }
+        for (i, bone) in hrc.node.iter().enumerate() {
+            let parent = usize::try_from(bone.parent)
+                .ok()
+                .filter(|&x| x < hrc.node.len())
+                .map_or(root, |x| first + x);
+            builder.set_parent(first + i, parent);
+        }
+    }
+    // Names that match nothing leave the node at the root; its extras still
+    // hold the name.
+    for (node, parent_name) in parent_names {
+        if let Some(&parent) = names.get(parent_name) {
+            builder.set_parent(node, parent);
+        }
+    }
+    builder.finish()
+}
+
+/// Converts frames to glTF times in seconds.
+struct Timing {
+    begin: f32,
+    fps: f32,
+}
+
+impl Timing {
+    fn times(&self, frames: &[f32]) -> Vec<f32> {
+        frames.iter().map(|x| (x - self.begin) / self.fps).collect()
+    }
+}
+
+/// The curves that animate an object or bone.
+struct Transform<'a> {
+    rot: &'a Vec3,
+    scale: &'a Vec3,
+    trans: &'a Vec3,
+    visibility: &'a Curve,
+}
+
+/// Adds a node animated by `transform`, returning its index.
+fn export_node(
+    builder: &mut Builder,
+    name: &str,
+    extras: Json,
+    transform: Transform,
+    timing: &Timing,
+    options: &GltfOptions,
+) -> usize {
+    let node = builder.node(json!({ "name": name, "extras": extras }));
+    export_vec3(builder, node, "translation", transform.trans, 0.0, timing);
+    export_rotation(builder, node, transform.rot, timing);
+    export_vec3(builder, node, "scale", transform.scale, 1.0, timing);
+    export_visibility(builder, node, transform.visibility, timing, options);
+    node
+}
+
+/// The frames of every key of `curves`, in order and without repeats.
+fn key_frames(curves: &[&Curve]) -> Vec<f32> {
+    let mut frames: Vec<_> = curves
+        .iter()
+        .flat_map(|x| x.keys())
+        .map(Key::frame)
+        .collect();
+    frames.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    frames.dedup();
+    frames
+}
+
+/// Every whole frame from the first to the last of `frames`, for baking.
+fn every_frame(frames: &[f32]) -> Vec<f32> {
+    let (first, last) = (frames[0].floor(), frames[frames.len() - 1].ceil());
+    (0..=(last - first) as usize)
+        .map(|i| first + i as f32)
+        .collect()
+}
+
+fn export_vec3(
+    builder: &mut Builder,
+    node: usize,
+    path: &str,
+    v: &Vec3,
+    default: f32,
+    timing: &Timing,
+) {
+    let rest = sample(v, timing.begin, default);
+    if rest != [default; 3] {
+        builder.nodes[node][path] = json!(rest);
+    }
+    let curves = [&v.x, &v.y, &v.z];
+    let frames = key_frames(&curves);
+    if frames.is_empty() {
+        return;
+    }
+    let target = json!({ "node": node, "path": path });
+
+    let hold = |x: &&Curve| matches!(x, Curve::Hold { .. });
+    let animated: Vec<_> = curves.iter().filter(|x| !x.keys().is_empty()).collect();
+    if animated.iter().any(|x| hold(x)) {
+        // Hold keys step; mixed with smooth curves, neither sampler fits, so
+        // every frame is baked.
+        let (frames, interpolation) = match animated.iter().all(|x| hold(x)) {
+            true => (frames, "STEP"),
+            false => (every_frame(&frames), "LINEAR"),
+        };
+        let values: Vec<_> = frames.iter().flat_map(|&x| sample(v, x, default)).collect();
+        let input = builder.accessor(&timing.times(&frames), "SCALAR");
+        let output = builder.accessor(&values, "VEC3");
+        builder.channel(input, output, interpolation, target);
+        return;
+    }
+
+    // Each knot is exact for its curve, even at frames another axis has a
+    // key at, so splitting a segment there keeps its shape.
+    let knots: Vec<_> = frames
+        .iter()
+        .map(|&frame| curves.map(|x| knot(x, frame, default)))
+        .collect();
+    let mut values = Vec::with_capacity(knots.len() * 9);
+    for knot in &knots {
+        values.extend(knot.iter().map(|x| x[0] * timing.fps));
+        values.extend(knot.iter().map(|x| x[1]));
+        values.extend(knot.iter().map(|x| x[2] * timing.fps));
+    }
+    let input = builder.accessor(&timing.times(&frames), "SCALAR");
+    let output = builder.accessor(&values, "VEC3");
+    builder.channel(input, output, "CUBICSPLINE", target);
+}
+
+/// The incoming tangent, value and outgoing tangent of `curve` at `frame`,
+/// with tangents in value per frame. Curves are flat outside their keys.
+fn knot(curve: &Curve, frame: f32, default: f32) -> [f32; 3] {
+    let value = curve.value_at(frame).unwrap_or(default);
+    let keys = curve.keys();
+    let value_of = |i: usize| keys[i].value().unwrap_or(0.0);
+    let slope =
+        |i: usize| (value_of(i + 1) - value_of(i)) / (keys[i + 1].frame() - keys[i].frame());
+    let linear = matches!(curve, Curve::Linear { .. });
+
+    if let Some(i) = keys.iter().position(|x| x.frame() == frame) {
+        let (in_tangent, out_tangent) = keys[i].tangents();
+        let in_tangent = match i {
+            0 => 0.0,
+            _ if linear => slope(i - 1),
+            _ => in_tangent,
+        };
+        let out_tangent = match i + 1 == keys.len() {
+            true => 0.0,
+            false if linear => slope(i),
+            false => out_tangent,
+        };
+        return [in_tangent, value, out_tangent];
+    }
+    let next = keys.iter().position(|x| x.frame() > frame);
+    let i = match next {
+        Some(i) if i > 0 => i - 1,
+        _ => return [0.0, value, 0.0],
+    };
+    if linear {
+        return [slope(i), value, slope(i)];
+    }
+    let (k0, k1) = (keys[i], keys[i + 1]);
+    let span = k1.frame() - k0.frame();
+    let t = (frame - k0.frame()) / span;
+    let (_, out_tangent) = k0.tangents();
+    let (in_tangent, _) = k1.tangents();
+    let tangent =
+        (value_of(i) * (6.0 * t * t - 6.0 * t) + value_of(i + 1) * (6.0 * t - 6.0 * t * t)) / span
+            + out_tangent * (3.0 * t * t - 4.0 * t + 1.0)
+            + in_tangent * (3.0 * t * t - 2.0 * t);
+    [tangent, value, tangent]
+}
+
+fn export_rotation(builder: &mut Builder, node: usize, rot: &Vec3, timing: &Timing) {
+    let rest = euler(sample(rot, timing.begin, 0.0));
+    if rest != [0.0, 0.0, 0.0, 1.0] {
+        builder.nodes[node]["rotation"] = json!(rest);
+    }
+    let curves = [&rot.x, &rot.y, &rot.z];
+    let frames = key_frames(&curves);
+    if frames.is_empty() {
+        return;
+    }
+    let target = json!({ "node": node, "path": "rotation" });
+
+    let hold = |x: &&Curve| matches!(x, Curve::Hold { .. });
+    let animated: Vec<_> = curves.iter().filter(|x| !x.keys().is_empty()).collect();
+    if animated.iter().any(|x| hold(x)) {
+        let (frames, interpolation) = match animated.iter().all(|x| hold(x)) {
+            true => (frames, "STEP"),
+            false => (every_frame(&frames), "LINEAR"),
+        };
+        let mut values = Vec::with_capacity(frames.len() * 4);
+        let mut previous = [0.0, 0.0, 0.0, 1.0];
+        for &frame in &frames {
+            let mut rotation = euler(sample(rot, frame, 0.0));
+            if dot4(previous, rotation) < 0.0 {
+                rotation = rotation.map(|x| -x);
+            }
+            previous = rotation;
+            values.extend_from_slice(&rotation);
+        }
+        let input = builder.accessor(&timing.times(&frames), "SCALAR");
+        let output = builder.accessor(&values, "VEC4");
+        builder.channel(input, output, interpolation, target);
+        return;
+    }
+
+    // The value and slope of each knot are exact, but a cubic between two
+    // quaternions only follows the turn of the angles closely over short
+    // arcs, so long segments get extra knots.
+    let frames = subdivide(&curves, &frames);
+    let mut values = Vec::with_capacity(frames.len() * 12);
+    let mut previous = [0.0, 0.0, 0.0, 1.0];
+    for &frame in &frames {
+        let knots = curves.map(|x| knot(x, frame, 0.0));
+        let angles = knots.map(|x| x[1]);
+        let mut rotation = [
+            euler_rate(angles, knots.map(|x| x[0] * timing.fps)),
+            euler(angles),
+            euler_rate(angles, knots.map(|x| x[2] * timing.fps)),
+        ];
+        if dot4(previous, rotation[1]) < 0.0 {
+            rotation = rotation.map(|x| x.map(|x| -x));
+        }
+        previous = rotation[1];
+        values.extend(rotation.iter().flatten());
+    }
+    let input = builder.accessor(&timing.times(&frames), "SCALAR");
+    let output = builder.accessor(&values, "VEC4");
+    builder.channel(input, output, "CUBICSPLINE", target);
+}
+
+/// `frames` with knots added between any two of them that a curve turns more
+/// than an eighth of a turn between.
+fn subdivide(curves: &[&Curve; 3], frames: &[f32]) -> Vec<f32> {
+    let mut subdivided = vec![frames[0]];
+    for pair in frames.windows(2) {
+        let turn = curves
+            .iter()
+            .map(|x| {
+                (x.value_at(pair[1]).unwrap_or(0.0) - x.value_at(pair[0]).unwrap_or(0.0)).abs()
+            })
+            .fold(0.0, f32::max);
+        let count = (turn / std::f32::consts::FRAC_PI_4).ceil().max(1.0) as usize;
+        let step = (pair[1] - pair[0]) / count as f32;
+        subdivided.extend((1..count).map(|i| pair[0] + step * i as f32));
+        subdivided.push(pair[1]);
+    }
+    subdivided
+}
+
+/// How fast `euler(angles)` changes while the angles change at `rates`.
+fn euler_rate(angles: [f32; 3], rates: [f32; 3]) -> Quat {
+    let axes = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    let q = |i: usize| axis_angle(axes[i], angles[i]);
+    // Half a turn further along is the derivative of the half angle form.
+    let dq = |i: usize| {
+        axis_angle(axes[i], angles[i] + std::f32::consts::PI).map(|x| x * 0.5 * rates[i])
+    };
+    let terms = [
+        mul(dq(2), mul(q(1), q(0))),
+        mul(q(2), mul(dq(1), q(0))),
+        mul(q(2), mul(q(1), dq(0))),
+    ];
+    [0, 1, 2, 3].map(|i| terms.iter().map(|x| x[i]).sum())
+}
+
+fn export_visibility(
+    builder: &mut Builder,
+    node: usize,
+    visibility: &Curve,
+    timing: &Timing,
+    options: &GltfOptions,
+) {
+    let visible = |frame| visibility.value_at(frame).map_or(true, |x| x >= 0.5);
+    let frames = key_frames(&[visibility]);
+    if frames.is_empty() && visible(timing.begin) {
+        return;
+    }
+    builder.extension("KHR_node_visibility");
+    builder.nodes[node]["extensions"] = json!({
+        "KHR_node_visibility": { "visible": visible(timing.begin) },
+    });
+    if frames.is_empty() || !options.animation_pointer {
+        return;
+    }
+    let values: Vec<_> = frames.iter().map(|&x| u8::from(visible(x))).collect();
+    let input = builder.accessor(&timing.times(&frames), "SCALAR");
+    let output = builder.flag_accessor(&values);
+    let pointer = format!("/nodes/{}/extensions/KHR_node_visibility/visible", node);
+    builder.pointer_channel(input, output, "STEP", pointer);
+}
+
+fn export_camera(
+    builder: &mut Builder,
+    index: usize,
+    camera: &CameraRoot,
+    frames: &[f32],
+    input: usize,
+    options: &GltfOptions,
+) {
+    let view_point = &camera.view_point;
+    let mut translations = Vec::with_capacity(frames.len() * 3);
+    let mut rotations = Vec::with_capacity(frames.len() * 4);
+    let mut yfovs = Vec::with_capacity(frames.len());
+    let mut previous = [0.0, 0.0, 0.0, 1.0];
+    for &frame in frames {
+        let pose = camera_pose(camera, frame);
+        let eye = pose.eye;
+        let mut rotation = pose.rotation();
+        // Keep neighbouring keys in the same hemisphere so they interpolate
+        // the short way round.
+        if dot4(previous, rotation) < 0.0 {
+            rotation = rotation.map(|x| -x);
+        }
+        previous = rotation;
+        translations.extend_from_slice(&eye);
+        rotations.extend_from_slice(&rotation);
+        yfovs.push(yfov(view_point, frame));
+    }
+
+    let node = builder.node(json!({
+        "name": format!("camera_root.{}", index),
+        "camera": builder.cameras.len(),
+        "translation": &translations[..3],
+        "rotation": &rotations[..4],
+    }));
+    builder.cameras.push(json!({
+        "type": "perspective",
+        "perspective": {
+            "aspectRatio": view_point.aspect,
+            "yfov": yfovs[0],
+            "znear": 0.01,
+        },
+    }));
+
+    let output = builder.accessor(&translations, "VEC3");
+    let target = json!({ "node": node, "path": "translation" });
+    builder.channel(input, output, "LINEAR", target);
+    let output = builder.accessor(&rotations, "VEC4");
+    let target = json!({ "node": node, "path": "rotation" });
+    builder.channel(input, output, "LINEAR", target);
+    let animated = |x: &Option<Curve>| x.as_ref().is_some_and(|x| !x.keys().is_empty());
+    if options.animation_pointer
+        && (animated(&view_point.fov) || animated(&view_point.focal_length))
+    {
+        let pointer = format!("/cameras/{}/perspective/yfov", builder.cameras.len() - 1);
+        let output = builder.accessor(&yfovs, "SCALAR");
+        builder.pointer_channel(input, output, "LINEAR", pointer);
+    }
+}
+
+/// The dot product of two quaternions, negative when they face apart.
+fn dot4(a: Quat, b: Quat) -> f32 {
+    a.iter().zip(&b).map(|(a, b)| a * b).sum()
+}
+
+/// Collects the parts of a glTF asset as they are added.
+#[derive(Default)]
+struct Builder {
+    nodes: Vec<Json>,
+    parents: Vec<Option<usize>>,
+    cameras: Vec<Json>,
+    accessors: Vec<Json>,
+    buffer_views: Vec<Json>,
+    buffer: Vec<u8>,
+    channels: Vec<Json>,
+    samplers: Vec<Json>,
+    extensions_used: Vec<&'static str>,
+}
+
+impl Builder {
+    fn node(&mut self, node: Json) -> usize {
+        self.nodes.push(node);
+        self.parents.push(None);
+        self.nodes.len() - 1
+    }
+
+    /// Makes `child` a child of `parent`, unless that would make a cycle.
+    fn set_parent(&mut self, child: usize, parent: usize) {
+        let mut ancestor = Some(parent);
+        while let Some(x) = ancestor {
+            if x == child {
+                return;
+            }
+            ancestor = self.parents[x];
+        }
+        self.parents[child] = Some(parent);
+    }
+
+    fn extension(&mut self, name: &'static str) {
+        if !self.extensions_used.contains(&name) {
+            self.extensions_used.push(name);
+        }
+    }
+
+    /// Appends `data` to the buffer as floats of the accessor type `kind`,
+    /// returning the accessor's index.
+    fn accessor(&mut self, data: &[f32], kind: &str) -> usize {
+        let components = match kind {
+            "SCALAR" => 1,
+            "VEC3" => 3,
+            "VEC4" => 4,
+            _ => unreachable!(),
+        };
+        let mut min = vec![f32::INFINITY; components];
+        let mut max = vec![f32::NEG_INFINITY; components];
+        for item in data.chunks(components) {
+            for (i, &x) in item.iter().enumerate() {
+                min[i] = min[i].min(x);
+                max[i] = max[i].max(x);
+            }
+        }
+
+        self.buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": self.buffer.len(),
+            "byteLength": data.len() * 4,
+        }));
+        for x in data {
+            self.buffer.extend_from_slice(&x.to_le_bytes());
+        }
+        self.accessors.push(json!({
+            "bufferView": self.buffer_views.len() - 1,
+            "componentType": 5126,
+            "count": data.len() / components,
+            "type": kind,
+            "min": min,
+            "max": max,
+        }));
+        self.accessors.len() - 1
+    }
+
+    /// Appends booleans as unsigned bytes, returning the accessor's index.
+    fn flag_accessor(&mut self, data: &[u8]) -> usize {
+        self.buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": self.buffer.len(),
+            "byteLength": data.len(),
+        }));
+        self.buffer.extend_from_slice(data);
+        pad(&mut self.buffer, 0);
+        self.accessors.push(json!({
+            "bufferView": self.buffer_views.len() - 1,
+            "componentType": 5121,
+            "count": data.len(),
+            "type": "SCALAR",
+        }));
+        self.accessors.len() - 1
+    }
+
+    fn channel(&mut self, input: usize, output: usize, interpolation: &str, target: Json) {
+        self.samplers.push(json!({
+            "input": input,
+            "output": output,
+            "interpolation": interpolation,
+        }));
+        self.channels.push(json!({
+            "sampler": self.samplers.len() - 1,
+            "target": target,
+        }));
+    }
+
+    fn pointer_channel(
+        &mut self,
+        input: usize,
+        output: usize,
+        interpolation: &str,
+        pointer: String,
+    ) {
+        self.extension("KHR_animation_pointer");
+        let target = json!({
+            "path": "pointer",
+            "extensions": { "KHR_animation_pointer": { "pointer": pointer } },
+        });
+        self.channel(input, output, interpolation, target);
+    }
+
+    fn finish(mut self) -> Gltf {
+        let mut children = vec![Vec::new(); self.nodes.len()];
+        for (child, parent) in self.parents.iter().enumerate() {
+            if let Some(parent) = parent {
+                children[*parent].push(child);
+            }
+        }
+        for (node, children) in self.nodes.iter_mut().zip(children) {
+            if !children.is_empty() {
+                node["children"] = json!(children);
+            }
+        }
+        let mut json = Map::new();
+        json.insert(
+            "asset".to_string(),
+            json!({ "version": "2.0", "generator": "serde_a3da" }),
+        );
+        if !self.extensions_used.is_empty() {
+            json.insert("extensionsUsed".to_string(), json!(self.extensions_used));
+        }
+        json.insert("scene".to_string(), json!(0));
+        let roots: Vec<_> = (0..self.nodes.len())
+            .filter(|&x| self.parents[x].is_none())
+            .collect();
+        json.insert("scenes".to_string(), json!([{ "nodes": roots }]));
+        let mut insert = |key: &str, values: Vec<Json>| {
+            if !values.is_empty() {
+                json.insert(key.to_string(), Json::Array(values));
+            }
+        };
+        insert("nodes", self.nodes);
+        insert("cameras", self.cameras);
+        insert("accessors", self.accessors);
+        insert("bufferViews", self.buffer_views);
+        if !self.buffer.is_empty() {
+            insert("buffers", vec![json!({ "byteLength": self.buffer.len() })]);
+        }
+        if !self.channels.is_empty() {
+            let animation = json!({
+                "name": "a3da",
+                "channels": self.channels,
+                "samplers": self.samplers,
+            });
+            insert("animations", vec![animation]);
+        }
+        Gltf {
+            json: Json::Object(json),
+            buffer: self.buffer,
+        }
+    }
+}
+
+/// Pads a GLB chunk to a multiple of four bytes.
+fn pad(chunk: &mut Vec<u8>, byte: u8) {
+    while chunk.len() % 4 != 0 {
+        chunk.push(byte);
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &x)| n | u32::from(x) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                output.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+#[test]
+fn export_cameras() {
+    use crate::model::Key;
+
+    let input = std::fs::read_to_string("assets/CAMPV001_BASE.a3da").unwrap();
+    let mut a3da: A3da = crate::from_str(&input).unwrap();
+    let gltf = export_gltf(&a3da, &GltfOptions::new());
+    let json = gltf.json();
+    assert_eq!(json["nodes"][0]["camera"], 0);
+    assert_eq!(json["cameras"][0]["perspective"]["aspectRatio"], 1.77778f32);
+    let channels = json["animations"][0]["channels"].as_array().unwrap();
+    assert_eq!(channels.len(), 2);
+    assert_eq!(json["accessors"][0]["count"], 9301);
+    assert_eq!(json["accessors"][0]["max"][0], 155.0);
+    assert_eq!(gltf.buffer().len(), 9301 * (1 + 3 + 4) * 4);
+    let eye = &json["nodes"][0]["translation"];
+    assert_eq!(eye[0], -1.59852f32);
+    let rotation: Vec<_> = (0..4)
+        .map(|i| json["nodes"][0]["rotation"][i].as_f64().unwrap())
+        .collect();
+    let length: f64 = rotation.iter().map(|x| x * x).sum();
+    assert!((length - 1.0).abs() < 1e-5);
+
+    let glb = gltf.to_glb();
+    assert_eq!(&glb[..4], b"glTF");
+    assert_eq!(
+        u32::from_le_bytes([glb[8], glb[9], glb[10], glb[11]]) as usize,
+        glb.len()
+    );
+    assert!(gltf
+        .to_gltf()
+        .contains("\"uri\":\"data:application/octet-stream;base64,"));
+    assert_eq!(base64(b"glTF!"), "Z2xURiE=");
+
+    a3da.camera_root[0].view_point.fov = Some(Curve::Linear {
+        key: vec![Key::Linear(0.0, 0.5), Key::Linear(100.0, 1.0)],
+        max: 100.0,
+    });
+    let json = export_gltf(&a3da, &GltfOptions::new()).json().clone();
+    assert_eq!(json["extensionsUsed"][0], "KHR_animation_pointer");
+    let pointer = &json["animations"][0]["channels"][2]["target"]["extensions"];
+    assert_eq!(
+        pointer["KHR_animation_pointer"]["pointer"],
+        "/cameras/0/perspective/yfov"
+    );
+    let json = export_gltf(&a3da, &GltfOptions::new().animation_pointer(false))
+        .json()
+        .clone();
+    assert!(json.get("extensionsUsed").is_none());
+}
+
+#[test]
+fn export_objects() {
+    use crate::model::{Key, Object, ObjectHrc, ObjectNode};
+
+    let input = std::fs::read_to_string("assets/CAMPV001_BASE.a3da").unwrap();
+    let mut a3da: A3da = crate::from_str(&input).unwrap();
+    a3da.camera_root.clear();
+    let none = || Vec3 {
+        x: Curve::None {},
+        y: Curve::None {},
+        z: Curve::None {},
+    };
+    let mut trans = none();
+    trans.x = Curve::Hermite {
+        key: vec![Key::Hermite(0.0, 0.0, 0.0), Key::Hermite(10.0, 1.0, 0.1)],
+        max: 10.0,
+    };
+    trans.y = Curve::Static { value: 2.0 };
+    a3da.object.push(Object {
+        morph: None,
+        morph_offset: None,
+        name: "prop".to_string(),
+        parent_name: Some("body".to_string()),
+        rot: none(),
+        scale: none(),
+        trans,
+        uid_name: "PROP".to_string(),
+        visibility: Curve::Hold {
+            key: vec![Key::Linear(0.0, 0.0), Key::Linear(5.0, 1.0)],
+            max: 5.0,
+        },
+    });
+    let mut rot = none();
+    rot.y = Curve::Static {
+        value: std::f32::consts::FRAC_PI_2,
+    };
+    let node = |name: &str, parent, rot| ObjectNode {
+        name: name.to_string(),
+        parent,
+        rot,
+        scale: none(),
+        trans: none(),
+        visibility: Curve::Static { value: 1.0 },
+    };
+    let mut root = node("root", -1, none());
+    let spin = Curve::Hermite {
+        key: vec![
+            Key::Hermite(0.0, 0.0, 0.1),
+            Key::Hermite(10.0, std::f32::consts::PI, 0.0),
+        ],
+        max: 10.0,
+    };
+    root.rot.z = spin.clone();
+    root.scale.x = Curve::Hold {
+        key: vec![Key::Linear(0.0, 1.0), Key::Linear(4.0, 2.0)],
+        max: 4.0,
+    };
+    root.scale.y = Curve::Hermite {
+        key: vec![Key::Hermite(0.0, 1.0, 0.0), Key::Hermite(4.0, 3.0, 0.0)],
+        max: 4.0,
+    };
+    a3da.objhrc.push(ObjectHrc {
+        name: "chara".to_string(),
+        node: vec![root, node("body", 0, rot)],
+        parent_name: None,
+        uid_name: "CHARA".to_string(),
+    });
+
+    let gltf = export_gltf(&a3da, &GltfOptions::new());
+    let json = gltf.json();
+    let nodes = json["nodes"].as_array().unwrap();
+    let names: Vec<_> = nodes.iter().map(|x| x["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["prop", "chara", "root", "body"]);
+    assert_eq!(json["scenes"][0]["nodes"], json!([1]));
+    assert_eq!(nodes[1]["children"], json!([2]));
+    assert_eq!(nodes[2]["children"], json!([3]));
+    assert_eq!(nodes[3]["children"], json!([0]));
+    assert_eq!(nodes[0]["extras"]["parent_name"], "body");
+    assert_eq!(nodes[0]["translation"], json!([0.0, 2.0, 0.0]));
+    let half = std::f32::consts::FRAC_1_SQRT_2;
+    let rotation = nodes[3]["rotation"].as_array().unwrap();
+    let rotation: Vec<_> = rotation
+        .iter()
+        .map(|x| x.as_f64().unwrap() as f32)
+        .collect();
+    assert!(rotation
+        .iter()
+        .zip(&[0.0, half, 0.0, half])
+        .all(|(a, b)| (a - b).abs() < 1e-6));
+
+    let floats = |accessor: &Json| {
+        let view = &json["bufferViews"][accessor["bufferView"].as_u64().unwrap() as usize];
+        let offset = view["byteOffset"].as_u64().unwrap() as usize;
+        let width = match accessor["type"].as_str().unwrap() {
+            "VEC4" => 4,
+            _ => 3,
+        };
+        let count = accessor["count"].as_u64().unwrap() as usize * width;
+        (0..count)
+            .map(|i| {
+                let bytes = &gltf.buffer()[offset + i * 4..offset + i * 4 + 4];
+                f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+            })
+            .collect::<Vec<_>>()
+    };
+    let animation = &json["animations"][0];
+    let channels = animation["channels"].as_array().unwrap();
+    assert_eq!(channels.len(), 4);
+    assert_eq!(
+        channels[0]["target"],
+        json!({ "node": 0, "path": "translation" })
+    );
+    let sampler = &animation["samplers"][0];
+    assert_eq!(sampler["interpolation"], "CUBICSPLINE");
+    let output = &json["accessors"][sampler["output"].as_u64().unwrap() as usize];
+    assert_eq!(output["count"], 6);
+    let values = floats(output);
+    assert_eq!(values[9..18], [6.0, 0.0, 0.0, 1.0, 2.0, 0.0, 0.0, 0.0, 0.0]);
+
+    assert_eq!(
+        nodes[0]["extensions"]["KHR_node_visibility"]["visible"],
+        false
+    );
+    let pointer = &channels[1]["target"]["extensions"]["KHR_animation_pointer"]["pointer"];
+    assert_eq!(pointer, "/nodes/0/extensions/KHR_node_visibility/visible");
+    assert_eq!(animation["samplers"][1]["interpolation"], "STEP");
+
+    // The rotation keeps its keys, with three added between them since it
+    // turns half a turn.
+    assert_eq!(
+        channels[2]["target"],
+        json!({ "node": 2, "path": "rotation" })
+    );
+    let sampler = &animation["samplers"][2];
+    assert_eq!(sampler["interpolation"], "CUBICSPLINE");
+    let input = &json["accessors"][sampler["input"].as_u64().unwrap() as usize];
+    assert_eq!(input["count"], 5);
+    let output = &json["accessors"][sampler["output"].as_u64().unwrap() as usize];
+    assert_eq!(output["count"], 15);
+    let values = floats(output);
+    let close = |a: &[f32], b: &[f32]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-5);
+    assert!(close(
+        &values[..12],
+        &[0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 3.0, 0.0]
+    ));
+    let halfway = euler([0.0, 0.0, spin.value_at(5.0).unwrap()]);
+    assert!(close(&values[2 * 12 + 4..2 * 12 + 8], &halfway));
+
+    // Between keys the spline stays close to the turn of the angle.
+    let span = 2.5 / 60.0;
+    let t: f32 = 0.5;
+    let (h00, h10) = (
+        2.0 * t.powi(3) - 3.0 * t * t + 1.0,
+        t.powi(3) - 2.0 * t * t + t,
+    );
+    let (h01, h11) = (-2.0 * t.powi(3) + 3.0 * t * t, t.powi(3) - t * t);
+    let q: Vec<_> = (0..4)
+        .map(|i| {
+            h00 * values[4 + i]
+                + h10 * span * values[8 + i]
+                + h01 * values[16 + i]
+                + h11 * span * values[12 + i]
+        })
+        .collect();
+    let norm = q.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let q: Vec<_> = q.iter().map(|x| x / norm).collect();
+    let expected = euler([0.0, 0.0, spin.value_at(1.25).unwrap()]);
+    assert!(q.iter().zip(&expected).all(|(a, b)| (a - b).abs() < 1e-3));
+
+    // The scale mixes a hold axis with a smooth one, so it is baked.
+    assert_eq!(channels[3]["target"], json!({ "node": 2, "path": "scale" }));
+    let sampler = &animation["samplers"][3];
+    assert_eq!(sampler["interpolation"], "LINEAR");
+    let output = &json["accessors"][sampler["output"].as_u64().unwrap() as usize];
+    assert_eq!(output["count"], 5);
+    assert_eq!(floats(output)[6..9], [1.0, 2.0, 1.0]);
+    let used = json["extensionsUsed"].as_array().unwrap();
+    assert!(used.contains(&json!("KHR_node_visibility")));
+}
@@ -0,0 +1,434 @@
+//! Conversion between A3DA text and JSON documents.
+//!
+//! Sections become objects, indexed sequences become arrays and `(…)` tuples
+//! become arrays of their items. An array of plain values reads back as a
+//! tuple, so the keys of sequences of plain values are listed under
+//! `"#sequences"` to read back as sequences. The `#` header lines are kept
+//! under the `"#"` key, and CRLF line endings under `"#line_ending"`.
+//!
+//! Any text `Document` reads converts back byte for byte. Files that
+//! `to_string` would not write as they are, because of comments, key order,
+//! whitespace around `=`, keys set twice or a missing final newline, also get
+//! a `"#lines"` array in place of `"#"` and `"#line_ending"`. It holds every
+//! line in file order: lines without a value of the tree, such as comments
+//! and keys set a second time, as strings, and the rest as the `[before,
+//! after]` text around their value, which is taken from the tree.
+
+use serde_json::{Number, Value as Json};
+
+use std::collections::BTreeMap;
+
+use crate::document::Document;
+use crate::error::{DeserializeError, SerializeError};
+use crate::reader::split_line;
+use crate::ser::{check_item, check_value, to_string_with, LineEnding, SerializerOptions};
+use crate::value::Value;
+
+const HEADER: &str = "#";
+const LINE_ENDING: &str = "#line_ending";
+const LINES: &str = "#lines";
+const SEQUENCES: &str = "#sequences";
+
+/// Controls how leaves are written by `to_json_with`.
+#[derive(Debug, Clone)]
+pub struct JsonOptions {
+    numbers: bool,
+}
+
+impl JsonOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Whether numeric leaves become JSON numbers. Only numbers that JSON
+    /// writes back exactly as they were written, such as `60` or `0.93616`,
+    /// are converted; `0.000` or `3.31402e-006` stay strings either way.
+    pub fn numbers(mut self, numbers: bool) -> Self {
+        self.numbers = numbers;
+        self
+    }
+}
+
+impl Default for JsonOptions {
+    fn default() -> Self {
+        Self { numbers: true }
+    }
+}
+
+/// Converts A3DA text to JSON with the default options.
+pub fn to_json(input: &str) -> Result<Json, DeserializeError> {
+    to_json_with(input, &JsonOptions::default())
+}
+
+pub fn to_json_with(input: &str, options: &JsonOptions) -> Result<Json, DeserializeError> {
+    let document = Document::parse(input)?;
+    let mut value = Value::default();
+    let mut lines = Vec::new();
+    for (text, entry) in document.lines() {
+        let line = match entry {
+            Some((key, range)) if insert_first(&mut value, key, &text[range.clone()]) => {
+                Json::from(vec![&text[..range.start], &text[range.end..]])
+            }
+            _ => Json::from(text),
+        };
+        lines.push(line);
+    }
+
+    let mut sequences = Vec::new();
+    let mut json = match section_to_json(&value, "", options, &mut sequences) {
+        Json::Object(x) => x,
+        _ => unreachable!(),
+    };
+    if !sequences.is_empty() {
+        json.insert(SEQUENCES.to_string(), Json::from(sequences));
+    }
+    let mut compact = json.clone();
+    let header: Vec<_> = document.header().map(Json::from).collect();
+    if !header.is_empty() {
+        compact.insert(HEADER.to_string(), Json::Array(header));
+    }
+    if input.contains("\r\n") {
+        compact.insert(LINE_ENDING.to_string(), Json::from("\r\n"));
+    }
+    let compact = Json::Object(compact);
+    if from_json(&compact).ok().as_deref() == Some(input) {
+        return Ok(compact);
+    }
+    json.insert(LINES.to_string(), Json::Array(lines));
+    Ok(Json::Object(json))
+}
+
+/// Sets `key` unless it is set already or a value or section is in the way,
+/// keeping the first line like `DuplicateKeys::FirstWins`.
+fn insert_first(value: &mut Value, key: &str, scalar: &str) -> bool {
+    let mut parent = value;
+    for name in key.split('.') {
+        parent = match parent {
+            Value::Section(x) => x.entry(name.to_string()).or_default(),
+            Value::Scalar(_) => return false,
+        };
+    }
+    match parent {
+        Value::Section(x) if x.is_empty() => {
+            *parent = Value::Scalar(scalar.to_string());
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Converts a JSON document made by `to_json` back to A3DA text.
+///
+/// With `"#lines"`, each line keeps its place and only the values changed in
+/// the tree are rewritten. Lines whose key is no longer in the tree are left
+/// out, and keys new to the tree are added at the end in key order.
+pub fn from_json(json: &Json) -> Result<String, SerializeError> {
+    let mut json = match json {
+        Json::Object(x) => x.clone(),
+        x => return Err(SerializeError::InvalidValue(x.to_string())),
+    };
+    let sequences = match json.remove(SEQUENCES) {
+        Some(Json::Array(x)) => x
+            .iter()
+            .map(|x| match x {
+                Json::String(x) => Ok(x.clone()),
+                x => Err(SerializeError::InvalidValue(x.to_string())),
+            })
+            .collect::<Result<_, _>>()?,
+        Some(x) => return Err(SerializeError::InvalidValue(x.to_string())),
+        None => Vec::new(),
+    };
+    if let Some(lines) = json.remove(LINES) {
+        let value = json_to_value(&Json::Object(json), "", &sequences)?;
+        return from_lines(&lines, value);
+    }
+    let line_ending = match json.remove(LINE_ENDING) {
+        None => LineEnding::Lf,
+        Some(x) if x == "\r\n" => LineEnding::CrLf,
+        Some(x) => return Err(SerializeError::InvalidValue(x.to_string())),
+    };
+    let eol = match line_ending {
+        LineEnding::Lf => "\n",
+        LineEnding::CrLf => "\r\n",
+    };
+
+    let mut output = String::new();
+    if let Some(header) = json.remove(HEADER) {
+        let lines = match &header {
+            Json::Array(x) => x,
+            x => return Err(SerializeError::InvalidValue(x.to_string())),
+        };
+        for line in lines {
+            let line = line
+                .as_str()
+                .ok_or_else(|| SerializeError::InvalidValue(line.to_string()))?;
+            output.push('#');
+            output.push_str(line);
+            output.push_str(eol);
+        }
+    }
+    let value = json_to_value(&Json::Object(json), "", &sequences)?;
+    let options = SerializerOptions::new().line_ending(line_ending);
+    output.push_str(&to_string_with(&value, &options)?);
+    Ok(output)
+}
+
+/// Writes the `"#lines"` layout of a document, taking values from `value`.
+fn from_lines(lines: &Json, mut value: Value) -> Result<String, SerializeError> {
+    let invalid = |x: &Json| SerializeError::InvalidValue(x.to_string());
+    let lines = lines.as_array().ok_or_else(|| invalid(lines))?;
+    let mut output = String::new();
+    for line in lines {
+        let (before, after) = match line {
+            Json::String(x) => {
+                output.push_str(x);
+                continue;
+            }
+            Json::Array(x) => match &x[..] {
+                [Json::String(before), Json::String(after)] => (before, after),
+                _ => return Err(invalid(line)),
+            },
+            _ => return Err(invalid(line)),
+        };
+        let (key, _) = split_line(before.trim()).ok_or_else(|| invalid(line))?;
+        let key = key.split('.').map(str::trim).collect::<Vec<_>>().join(".");
+        if let Some(Value::Scalar(_)) = value.get(&key) {
+            if let Some(Value::Scalar(scalar)) = value.remove(&key) {
+                check_value(&scalar)?;
+                output.push_str(before);
+                output.push_str(&scalar);
+                output.push_str(after);
+            }
+        }
+    }
+
+    let line_ending = match output.contains("\r\n") {
+        true => LineEnding::CrLf,
+        false => LineEnding::Lf,
+    };
+    let rest = to_string_with(&value, &SerializerOptions::new().line_ending(line_ending))?;
+    if !rest.is_empty() && !output.is_empty() && !output.ends_with('\n') {
+        output.push_str(match line_ending {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        });
+    }
+    output.push_str(&rest);
+    Ok(output)
+}
+
+/// Converts the value at `key`, adding the keys of sequences that would read
+/// back as tuples to `sequences`.
+fn section_to_json(
+    value: &Value,
+    key: &str,
+    options: &JsonOptions,
+    sequences: &mut Vec<String>,
+) -> Json {
+    let section = match value {
+        Value::Scalar(x) => return scalar_to_json(x, options),
+        Value::Section(x) => x,
+    };
+    match sequence(section) {
+        Some(elements) => {
+            let elements: Vec<_> = elements
+                .into_iter()
+                .enumerate()
+                .map(|(i, x)| section_to_json(x, &join(key, &i.to_string()), options, sequences))
+                .collect();
+            if !elements.is_empty() && elements.iter().all(is_item) {
+                sequences.push(key.to_string());
+            }
+            Json::Array(elements)
+        }
+        None => Json::Object(
+            section
+                .iter()
+                .map(|(name, x)| {
+                    let json = section_to_json(x, &join(key, name), options, sequences);
+                    (name.clone(), json)
+                })
+                .collect(),
+        ),
+    }
+}
+
+fn join(key: &str, name: &str) -> String {
+    match key {
+        "" => name.to_string(),
+        _ => format!("{}.{}", key, name),
+    }
+}
+
+/// The elements of a section holding exactly `0`..`length` and a canonical
+/// `length`.
+fn sequence(section: &BTreeMap<String, Value>) -> Option<Vec<&Value>> {
+    let length = section.get("length")?.as_str()?;
+    let len: usize = length.parse().ok()?;
+    if length != len.to_string() || section.len() != len + 1 {
+        return None;
+    }
+    (0..len).map(|i| section.get(&i.to_string())).collect()
+}
+
+fn scalar_to_json(scalar: &str, options: &JsonOptions) -> Json {
+    match scalar.strip_prefix('(').and_then(|x| x.strip_suffix(')')) {
+        Some(items) => Json::Array(items.split(',').map(|x| item_to_json(x, options)).collect()),
+        None => item_to_json(scalar, options),
+    }
+}
+
+fn item_to_json(item: &str, options: &JsonOptions) -> Json {
+    match item.parse::<Number>() {
+        Ok(x) if options.numbers && x.to_string() == item => Json::Number(x),
+        _ => Json::from(item),
+    }
+}
+
+/// Converts the JSON at `key`, reading arrays of plain values as tuples
+/// unless `sequences` lists their key.
+fn json_to_value(json: &Json, key: &str, sequences: &[String]) -> Result<Value, SerializeError> {
+    match json {
+        Json::Object(x) => Ok(Value::Section(
+            x.iter()
+                .map(|(name, x)| Ok((name.clone(), json_to_value(x, &join(key, name), sequences)?)))
+                .collect::<Result<_, SerializeError>>()?,
+        )),
+        Json::Array(x)
+            if !x.is_empty() && x.iter().all(is_item) && !sequences.iter().any(|x| x == key) =>
+        {
+            let items = x
+                .iter()
+                .map(|x| {
+                    let item = json_to_item(x)?;
+                    check_item(&item)?;
+                    Ok(item)
+                })
+                .collect::<Result<Vec<_>, SerializeError>>()?;
+            Ok(Value::Scalar(format!("({})", items.join(","))))
+        }
+        Json::Array(x) => {
+            let mut section: BTreeMap<_, _> = x
+                .iter()
+                .enumerate()
+                .map(|(i, x)| {
+                    let value = json_to_value(x, &join(key, &i.to_string()), sequences)?;
+                    Ok((i.to_string(), value))
+                })
+                .collect::<Result<_, SerializeError>>()?;
+            section.insert("length".to_string(), Value::Scalar(x.len().to_string()));
+            Ok(Value::Section(section))
+        }
+        x => json_to_item(x).map(Value::Scalar),
+    }
+}
+
+fn is_item(json: &Json) -> bool {
+    matches!(json, Json::String(_) | Json::Number(_))
+}
+
+fn json_to_item(json: &Json) -> Result<String, SerializeError> {
+    match json {
+        Json::String(x) => Ok(x.clone()),
+        Json::Number(x) => Ok(x.to_string()),
+        x => Err(SerializeError::InvalidValue(x.to_string())),
+    }
+}
+
+#[test]
+fn json_round_trip() {
+    let input = std::fs::read_to_string("assets/CAMPV001_BASE.a3da").unwrap();
+    let json = to_json(&input).unwrap();
+    assert_eq!(json["#"][1], "Wed Dec 30 15:32:02 2009");
+    assert_eq!(json["play_control"]["fps"], 60);
+    let view_point = &json["camera_root"][0]["view_point"];
+    assert_eq!(view_point["fov"]["value"], 0.93616);
+    assert_eq!(view_point["roll"]["key"][24]["data"], 5945);
+    let data = &json["camera_root"][0]["interest"]["trans"]["x"]["key"][1]["data"];
+    assert_eq!(*data, serde_json::json!([738, -0.522281, "3.31402e-006"]));
+    assert_eq!(from_json(&json).unwrap(), input);
+    assert!(json.get("#lines").is_none());
+
+    let strings = to_json_with(&input, &JsonOptions::new().numbers(false)).unwrap();
+    assert_eq!(strings["play_control"]["fps"], "60");
+    assert_eq!(from_json(&strings).unwrap(), input);
+
+    let input = "a.0=x\r\na.length=1\r\nb.c=0.000\r\nb.d=( 1,2)\r\n";
+    let json = to_json(input).unwrap();
+    let expected = serde_json::json!({
+        "#line_ending": "\r\n",
+        "#sequences": ["a"],
+        "a": ["x"],
+        "b": { "c": "0.000", "d": [" 1", 2] },
+    });
+    assert_eq!(json, expected);
+    assert_eq!(from_json(&json).unwrap(), input);
+
+    assert!(from_json(&serde_json::json!({ "a": true })).is_err());
+    assert!(from_json(&serde_json::json!({ "a.b": 1 })).is_err());
+}
+
+#[test]
+fn json_round_trip_sequences() {
+    let input = "a.0.name=x\na.0.tags.0=1\na.0.tags.1=2\na.0.tags.length=2\na.length=1\n\
+                 b.0=(0,1)\nb.1=(2,3)\nb.length=2\nc.length=0\nd=(4,5)\n";
+    let json = to_json(input).unwrap();
+    let expected = serde_json::json!({
+        "#sequences": ["a.0.tags"],
+        "a": [{ "name": "x", "tags": [1, 2] }],
+        "b": [[0, 1], [2, 3]],
+        "c": [],
+        "d": [4, 5],
+    });
+    assert_eq!(json, expected);
+    assert_eq!(from_json(&json).unwrap(), input);
+
+    let mut edited = json.clone();
+    edited["a"][0]["tags"] = serde_json::json!([7]);
+    assert_eq!(
+        from_json(&edited).unwrap(),
+        "a.0.name=x\na.0.tags.0=7\na.0.tags.length=1\na.length=1\n\
+         b.0=(0,1)\nb.1=(2,3)\nb.length=2\nc.length=0\nd=(4,5)\n"
+    );
+    assert!(from_json(&serde_json::json!({ "#sequences": [1] })).is_err());
+}
+
+#[test]
+fn json_round_trip_layout() {
+    let input = "#A3DA__________\r\n\r\nb = 2\r\n# note\r\na.x=1\r\n a . y =(0, 1)\r\n\
+                 a.x=3\r\na.y.z=4\r\nc.0=0.000";
+    let json = to_json(input).unwrap();
+    assert_eq!(json["a"], serde_json::json!({ "x": 1, "y": [0, " 1"] }));
+    assert_eq!(json["b"], 2);
+    assert_eq!(
+        json["#lines"],
+        serde_json::json!([
+            "#A3DA__________\r\n",
+            "\r\n",
+            ["b = ", "\r\n"],
+            "# note\r\n",
+            ["a.x=", "\r\n"],
+            [" a . y =", "\r\n"],
+            "a.x=3\r\n",
+            "a.y.z=4\r\n",
+            ["c.0=", ""],
+        ])
+    );
+    assert_eq!(from_json(&json).unwrap(), input);
+
+    let strings = to_json_with(input, &JsonOptions::new().numbers(false)).unwrap();
+    assert_eq!(from_json(&strings).unwrap(), input);
+
+    let mut edited = json.clone();
+    edited["b"] = serde_json::json!(5);
+    edited["c"].as_object_mut().unwrap().remove("0");
+    edited["d"] = serde_json::json!("x");
+    assert_eq!(
+        from_json(&edited).unwrap(),
+        "#A3DA__________\r\n\r\nb = 5\r\n# note\r\na.x=1\r\n a . y =(0, 1)\r\n\
+         a.x=3\r\na.y.z=4\r\nd=x\r\n"
+    );
+
+    edited["b"] = serde_json::json!(["1,2", 3]);
+    assert!(from_json(&edited).is_err());
+}
@@ -0,0 +1,263 @@
+use std::fmt;
+use std::iter::FromIterator;
+use std::str::FromStr;
+
+use crate::error::DeserializeError;
+
+/// One segment of a dotted key: a name, or the position of an element in a
+/// sequence.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum KeySegment {
+    Name(String),
+    Index(usize),
+}
+
+impl KeySegment {
+    /// Reads a segment, trimming whitespace like the deserializer does.
+    /// Digits without a leading zero are an index, anything else a name, so
+    /// every segment displays as it was parsed.
+    fn parse(segment: &str) -> Self {
+        let segment = segment.trim();
+        match parse_index(segment) {
+            Some(index) => KeySegment::Index(index),
+            None => KeySegment::Name(segment.to_string()),
+        }
+    }
+}
+
+/// Reads a segment as an index if it is digits without a leading zero, so
+/// `+1` and `01` stay names that cannot clash with `1`.
+pub(crate) fn parse_index(segment: &str) -> Option<usize> {
+    let canonical = !segment.starts_with('0') || segment == "0";
+    match segment.parse() {
+        Ok(index) if canonical && segment.bytes().all(|x| x.is_ascii_digit()) => Some(index),
+        _ => None,
+    }
+}
+
+impl From<usize> for KeySegment {
+    fn from(index: usize) -> Self {
+        KeySegment::Index(index)
+    }
+}
+
+impl From<&str> for KeySegment {
+    fn from(segment: &str) -> Self {
+        KeySegment::parse(segment)
+    }
+}
+
+impl fmt::Display for KeySegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeySegment::Name(x) => f.write_str(x),
+            KeySegment::Index(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+/// A parsed dotted key such as `camera_root.0.view_point.fov`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KeyPath {
+    segments: Vec<KeySegment>,
+}
+
+impl KeyPath {
+    /// The empty path, which is the prefix of every key.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn parse(key: &str) -> Result<Self, DeserializeError> {
+        let segments = key
+            .split('.')
+            .map(|x| match x.trim() {
+                "" => Err(DeserializeError::InvalidKey(key.to_string())),
+                x => Ok(KeySegment::parse(x)),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { segments })
+    }
+
+    pub fn segments(&self) -> &[KeySegment] {
+        &self.segments
+    }
+
+    pub fn len(&self) -> usize {
+        self.segments.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.segments.is_empty()
+    }
+
+    pub fn push(&mut self, segment: impl Into<KeySegment>) {
+        self.segments.push(segment.into());
+    }
+
+    pub fn pop(&mut self) -> Option<KeySegment> {
+        self.segments.pop()
+    }
+
+    /// This path with `segment` appended.
+    pub fn join(&self, segment: impl Into<KeySegment>) -> Self {
+        let mut path = self.clone();
+        path.push(segment);
+        path
+    }
+
+    /// The section this key is in, or `None` for the empty path.
+    pub fn parent(&self) -> Option<Self> {
+        let (_, segments) = self.segments.split_last()?;
+        Some(Self {
+            segments: segments.to_vec(),
+        })
+    }
+
+    pub fn last(&self) -> Option<&KeySegment> {
+        self.segments.last()
+    }
+
+    /// Whether this key is `prefix` or lies below it. Only whole segments
+    /// match, so `a.bc` does not start with `a.b`.
+    pub fn starts_with(&self, prefix: &KeyPath) -> bool {
+        self.segments.starts_with(&prefix.segments)
+    }
+
+    pub fn strip_prefix(&self, prefix: &KeyPath) -> Option<Self> {
+        let segments = self.segments.strip_prefix(&prefix.segments[..])?;
+        Some(Self {
+            segments: segments.to_vec(),
+        })
+    }
+}
+
+impl FromStr for KeyPath {
+    type Err = DeserializeError;
+
+    fn from_str(key: &str) -> Result<Self, Self::Err> {
+        Self::parse(key)
+    }
+}
+
+impl fmt::Display for KeyPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, segment) in self.segments.iter().enumerate() {
+            if i > 0 {
+                f.write_str(".")?;
+            }
+            write!(f, "{}", segment)?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: Into<KeySegment>> FromIterator<S> for KeyPath {
+    fn from_iter<I: IntoIterator<Item = S>>(iter: I) -> Self {
+        Self {
+            segments: iter.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+/// A key pattern for picking out leaves, such as
+/// `camera_root.*.view_point.fov.*` or `**.visibility.value`.
+///
+/// `*` matches exactly one segment and `**` matches any number of segments,
+/// including none. Other segments must match in full.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyGlob {
+    segments: Vec<GlobSegment>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum GlobSegment {
+    Key(KeySegment),
+    Any,
+    AnyDepth,
+}
+
+impl KeyGlob {
+    pub fn parse(pattern: &str) -> Result<Self, DeserializeError> {
+        let segments = KeyPath::parse(pattern)?
+            .segments
+            .into_iter()
+            .map(|x| match x {
+                KeySegment::Name(x) if x == "*" => GlobSegment::Any,
+                KeySegment::Name(x) if x == "**" => GlobSegment::AnyDepth,
+                x => GlobSegment::Key(x),
+            })
+            .collect();
+        Ok(Self { segments })
+    }
+
+    pub fn matches(&self, path: &KeyPath) -> bool {
+        matches(&self.segments, &path.segments)
+    }
+}
+
+fn matches(pattern: &[GlobSegment], path: &[KeySegment]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((GlobSegment::AnyDepth, rest)) => (0..=path.len()).any(|i| matches(rest, &path[i..])),
+        Some((GlobSegment::Any, rest)) => !path.is_empty() && matches(rest, &path[1..]),
+        Some((GlobSegment::Key(x), rest)) => path.first() == Some(x) && matches(rest, &path[1..]),
+    }
+}
+
+impl FromStr for KeyGlob {
+    type Err = DeserializeError;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        Self::parse(pattern)
+    }
+}
+
+#[test]
+fn key_paths() {
+    let path = KeyPath::parse("camera_root.0 . view_point.fov").unwrap();
+    assert_eq!(path.to_string(), "camera_root.0.view_point.fov");
+    assert_eq!(path.segments()[1], KeySegment::Index(0));
+    assert_eq!(path.len(), 4);
+
+    let prefix: KeyPath = "camera_root.0".parse().unwrap();
+    assert!(path.starts_with(&prefix));
+    assert!(path.starts_with(&KeyPath::new()));
+    assert!(!path.starts_with(&"camera_root.00".parse().unwrap()));
+    assert!(!path.starts_with(&"camera".parse().unwrap()));
+    assert_eq!(
+        path.strip_prefix(&prefix).unwrap().to_string(),
+        "view_point.fov"
+    );
+    assert_eq!(
+        path.parent().unwrap().join("fov_is_horizontal").to_string(),
+        "camera_root.0.view_point.fov_is_horizontal"
+    );
+    assert_eq!(
+        vec!["a", "01"].into_iter().collect::<KeyPath>().segments()[1],
+        KeySegment::Name("01".to_string())
+    );
+
+    assert!(KeyPath::parse("a..b").is_err());
+    assert!(KeyPath::parse("").is_err());
+}
+
+#[test]
+fn key_globs() {
+    let glob = |x: &str| KeyGlob::parse(x).unwrap();
+    let path = |x: &str| KeyPath::parse(x).unwrap();
+
+    let fov = glob("camera_root.*.view_point.fov.*");
+    assert!(fov.matches(&path("camera_root.0.view_point.fov.value")));
+    assert!(!fov.matches(&path("camera_root.0.view_point.fov")));
+    assert!(!fov.matches(&path("camera_root.0.view_point.fov.key.0.data")));
+
+    let visibility = glob("**.visibility.value");
+    assert!(visibility.matches(&path("visibility.value")));
+    assert!(visibility.matches(&path("camera_root.0.interest.visibility.value")));
+    assert!(!visibility.matches(&path("camera_root.0.visibility.type")));
+
+    assert!(glob("**").matches(&path("a.b")));
+    assert!(glob("a.**.d").matches(&path("a.b.c.d")));
+    assert!(!glob("a.**.d").matches(&path("a.b.c")));
+}
@@ -1,9 +1,56 @@
 // const DATE_FMT: &str = "%a %b %d %H:%%M:%S %Y";
 const DATE_FMT: &str = "%a %b %d %T %Y";
 
+mod csv;
 mod de;
+mod diff;
+mod document;
+mod encoding;
 mod error;
+#[cfg(feature = "gltf")]
+mod gltf;
+#[cfg(feature = "json")]
+mod json;
+mod key;
+mod math;
+mod merge;
+pub mod model;
+mod reader;
 mod ser;
+#[cfg(feature = "toml")]
+mod toml;
+mod value;
+mod vmd;
+#[cfg(feature = "yaml")]
+mod yaml;
+
+#[cfg(feature = "toml")]
+pub use self::toml::{from_toml, to_toml};
+pub use csv::to_csv;
+pub use de::{
+    from_reader, from_reader_with, from_str, from_str_lenient, from_str_with,
+    from_str_with_unknown, from_value, DeserializerOptions, DuplicateKeys,
+};
+pub use diff::{diff, Change};
+pub use document::Document;
+pub use encoding::{decode, encode, TextEncoding};
+pub use error::{DeserializeError, Diagnostic, SerializeError};
+#[cfg(feature = "gltf")]
+pub use gltf::{export_gltf, Gltf, GltfOptions};
+#[cfg(feature = "json")]
+pub use json::{from_json, to_json, to_json_with, JsonOptions};
+pub use key::{KeyGlob, KeyPath, KeySegment};
+pub use merge::{merge, Conflict};
+pub use model::A3da;
+pub use reader::{Event, RawValue, Reader};
+pub use ser::{
+    to_string, to_string_with, to_value, to_writer, to_writer_with, KeyOrder, LineEnding,
+    SerializerOptions, Timestamp,
+};
+pub use value::Value;
+pub use vmd::{from_vmd, to_vmd, VmdOptions};
+#[cfg(feature = "yaml")]
+pub use yaml::{from_yaml, to_yaml};
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,186 @@
+//! Vector, quaternion and camera math shared by the exporters.
+
+use crate::model::{CameraRoot, Vec3, ViewPoint};
+
+/// Vertical field of view of cameras that have neither a `fov` nor a focal
+/// length and aperture.
+const DEFAULT_YFOV: f32 = 0.7;
+
+/// A rotation as `[x, y, z, w]`, the order glTF uses.
+pub(crate) type Quat = [f32; 4];
+
+/// Where a camera is and where it looks at one frame, in world space.
+pub(crate) struct Pose {
+    pub eye: [f32; 3],
+    pub interest: [f32; 3],
+    /// The root's Y axis, which the camera keeps up before rolling.
+    pub up: [f32; 3],
+    pub roll: f32,
+}
+
+impl Pose {
+    /// The rotation of a node with the camera's view down its -Z axis.
+    pub fn rotation(&self) -> Quat {
+        look_at(self.eye, self.interest, self.up, self.roll)
+    }
+}
+
+/// The pose of `camera` at `frame`, with the root transform applied to the
+/// eye and interest.
+pub(crate) fn camera_pose(camera: &CameraRoot, frame: f32) -> Pose {
+    let rot = euler(sample(&camera.rot, frame, 0.0));
+    let world = |p: [f32; 3]| {
+        let scale = sample(&camera.scale, frame, 1.0);
+        let p = [p[0] * scale[0], p[1] * scale[1], p[2] * scale[2]];
+        add(sample(&camera.trans, frame, 0.0), rotate(rot, p))
+    };
+    Pose {
+        eye: world(sample(&camera.view_point.trans, frame, 0.0)),
+        interest: world(sample(&camera.interest.trans, frame, 0.0)),
+        up: rotate(rot, [0.0, 1.0, 0.0]),
+        roll: camera.view_point.roll.value_at(frame).unwrap_or(0.0),
+    }
+}
+
+pub(crate) fn yfov(view_point: &ViewPoint, frame: f32) -> f32 {
+    if let Some(fov) = view_point.fov.as_ref().and_then(|x| x.value_at(frame)) {
+        return match view_point.fov_is_horizontal {
+            true => 2.0 * ((fov / 2.0).tan() / view_point.aspect).atan(),
+            false => fov,
+        };
+    }
+    let focal_length = view_point
+        .focal_length
+        .as_ref()
+        .and_then(|x| x.value_at(frame));
+    match (focal_length, view_point.camera_aperture_h) {
+        (Some(focal_length), Some(aperture)) if focal_length > 0.0 => {
+            2.0 * (aperture * 25.4 / 2.0 / focal_length).atan()
+        }
+        _ => DEFAULT_YFOV,
+    }
+}
+
+pub(crate) fn sample(v: &Vec3, frame: f32, default: f32) -> [f32; 3] {
+    [&v.x, &v.y, &v.z].map(|x| x.value_at(frame).unwrap_or(default))
+}
+
+/// The rotation of a node looking from `eye` at `target`, turned by `roll`
+/// radians about the line of sight.
+pub(crate) fn look_at(eye: [f32; 3], target: [f32; 3], up: [f32; 3], roll: f32) -> Quat {
+    let forward = match normalize(sub(target, eye)) {
+        Some(x) => x,
+        None => return [0.0, 0.0, 0.0, 1.0],
+    };
+    let right = normalize(cross(forward, up))
+        .or_else(|| normalize(cross(forward, [0.0, 0.0, 1.0])))
+        .unwrap_or([1.0, 0.0, 0.0]);
+    let up = cross(right, forward);
+    let back = forward.map(|x| -x);
+    mul(
+        from_basis(right, up, back),
+        axis_angle([0.0, 0.0, 1.0], roll),
+    )
+}
+
+/// The rotation taking the X, Y and Z axes to the given orthonormal axes.
+pub(crate) fn from_basis(x: [f32; 3], y: [f32; 3], z: [f32; 3]) -> Quat {
+    let trace = x[0] + y[1] + z[2];
+    if trace > 0.0 {
+        let s = (trace + 1.0).sqrt() * 2.0;
+        [
+            (y[2] - z[1]) / s,
+            (z[0] - x[2]) / s,
+            (x[1] - y[0]) / s,
+            s / 4.0,
+        ]
+    } else if x[0] > y[1] && x[0] > z[2] {
+        let s = (1.0 + x[0] - y[1] - z[2]).sqrt() * 2.0;
+        [
+            s / 4.0,
+            (y[0] + x[1]) / s,
+            (z[0] + x[2]) / s,
+            (y[2] - z[1]) / s,
+        ]
+    } else if y[1] > z[2] {
+        let s = (1.0 + y[1] - x[0] - z[2]).sqrt() * 2.0;
+        [
+            (y[0] + x[1]) / s,
+            s / 4.0,
+            (z[1] + y[2]) / s,
+            (z[0] - x[2]) / s,
+        ]
+    } else {
+        let s = (1.0 + z[2] - x[0] - y[1]).sqrt() * 2.0;
+        [
+            (z[0] + x[2]) / s,
+            (z[1] + y[2]) / s,
+            s / 4.0,
+            (x[1] - y[0]) / s,
+        ]
+    }
+}
+
+/// Euler angles in radians applied in X, Y, Z order, Maya's default.
+pub(crate) fn euler(r: [f32; 3]) -> Quat {
+    let x = axis_angle([1.0, 0.0, 0.0], r[0]);
+    let y = axis_angle([0.0, 1.0, 0.0], r[1]);
+    let z = axis_angle([0.0, 0.0, 1.0], r[2]);
+    mul(z, mul(y, x))
+}
+
+pub(crate) fn axis_angle(axis: [f32; 3], angle: f32) -> Quat {
+    let (sin, cos) = (angle / 2.0).sin_cos();
+    [axis[0] * sin, axis[1] * sin, axis[2] * sin, cos]
+}
+
+pub(crate) fn mul(a: Quat, b: Quat) -> Quat {
+    [
+        a[3] * b[0] + a[0] * b[3] + a[1] * b[2] - a[2] * b[1],
+        a[3] * b[1] - a[0] * b[2] + a[1] * b[3] + a[2] * b[0],
+        a[3] * b[2] + a[0] * b[1] - a[1] * b[0] + a[2] * b[3],
+        a[3] * b[3] - a[0] * b[0] - a[1] * b[1] - a[2] * b[2],
+    ]
+}
+
+pub(crate) fn rotate(q: Quat, v: [f32; 3]) -> [f32; 3] {
+    let axis = [q[0], q[1], q[2]];
+    let t = cross(axis, v).map(|x| x * 2.0);
+    add(add(v, t.map(|x| x * q[3])), cross(axis, t))
+}
+
+pub(crate) fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+pub(crate) fn sub(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub(crate) fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub(crate) fn normalize(v: [f32; 3]) -> Option<[f32; 3]> {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    (length > 1e-6).then(|| v.map(|x| x / length))
+}
+
+#[test]
+fn camera_math() {
+    let close = |a: Quat, b: Quat| a.iter().zip(&b).all(|(a, b)| (a - b).abs() < 1e-6);
+    let forward = look_at([0.0; 3], [0.0, 0.0, -1.0], [0.0, 1.0, 0.0], 0.0);
+    assert!(close(forward, [0.0, 0.0, 0.0, 1.0]));
+    let left = look_at([0.0; 3], [-1.0, 0.0, 0.0], [0.0, 1.0, 0.0], 0.0);
+    let half = std::f32::consts::FRAC_1_SQRT_2;
+    assert!(close(left, [0.0, half, 0.0, half]));
+    let v = rotate(
+        euler([0.0, 0.0, std::f32::consts::FRAC_PI_2]),
+        [1.0, 0.0, 0.0],
+    );
+    assert!((v[1] - 1.0).abs() < 1e-6);
+}
@@ -0,0 +1,280 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::de::from_value;
+use crate::diff::diff;
+use crate::key::KeyPath;
+use crate::model::Key;
+use crate::value::Value;
+
+/// A key both sides of a `merge` changed in different ways. The merged tree
+/// keeps `ours`; `None` means that side does not have the key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub key: KeyPath,
+    /// The frame of a conflicting keyframe when `key` is a curve's `key`
+    /// sequence.
+    pub frame: Option<f32>,
+    pub base: Option<Value>,
+    pub ours: Option<Value>,
+    pub theirs: Option<Value>,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.key)?;
+        if let Some(frame) = self.frame {
+            write!(f, " @ {}", frame)?;
+        }
+        let show = |x: &Option<Value>| match x {
+            None => "(none)".to_string(),
+            Some(Value::Scalar(x)) => x.clone(),
+            Some(x) => {
+                let leaves: Vec<_> = x
+                    .leaves()
+                    .iter()
+                    .map(|(k, v)| format!("{}={}", k, v))
+                    .collect();
+                format!("{{{}}}", leaves.join(", "))
+            }
+        };
+        write!(
+            f,
+            ": base {}, ours {}, theirs {}",
+            show(&self.base),
+            show(&self.ours),
+            show(&self.theirs)
+        )
+    }
+}
+
+/// Merges the changes `ours` and `theirs` each made to `base`.
+///
+/// A key changed on one side only takes that side's value, so edits to
+/// different sections combine. Keyframes of a curve are matched by frame, so
+/// keys inserted on both sides at different frames all survive. Keys changed
+/// differently on both sides are conflicts; the merged tree keeps `ours` for
+/// them and each is listed with all three versions.
+pub fn merge(base: &Value, ours: &Value, theirs: &Value) -> (Value, Vec<Conflict>) {
+    let mut conflicts = Vec::new();
+    let merged = merge_values(
+        &KeyPath::new(),
+        Some(base),
+        Some(ours),
+        Some(theirs),
+        &mut conflicts,
+    );
+    (merged.unwrap_or_default(), conflicts)
+}
+
+fn same(a: Option<&Value>, b: Option<&Value>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => diff(a, b).is_empty(),
+        (a, b) => a.is_none() && b.is_none(),
+    }
+}
+
+fn merge_values(
+    key: &KeyPath,
+    base: Option<&Value>,
+    ours: Option<&Value>,
+    theirs: Option<&Value>,
+    conflicts: &mut Vec<Conflict>,
+) -> Option<Value> {
+    if same(ours, theirs) || same(base, theirs) {
+        return ours.cloned();
+    }
+    if same(base, ours) {
+        return theirs.cloned();
+    }
+    match (section(base), section(ours), section(theirs)) {
+        (Some(base), Some(Some(ours)), Some(Some(theirs))) => {
+            let empty = BTreeMap::new();
+            let base = base.unwrap_or(&empty);
+            Some(Value::Section(merge_sections(
+                key, base, ours, theirs, conflicts,
+            )))
+        }
+        _ => {
+            conflicts.push(Conflict {
+                key: key.clone(),
+                frame: None,
+                base: base.cloned(),
+                ours: ours.cloned(),
+                theirs: theirs.cloned(),
+            });
+            ours.cloned()
+        }
+    }
+}
+
+/// A section, or no value at all, which merges like an empty section.
+fn section(value: Option<&Value>) -> Option<Option<&BTreeMap<String, Value>>> {
+    match value {
+        None => Some(None),
+        Some(Value::Section(x)) => Some(Some(x)),
+        Some(Value::Scalar(_)) => None,
+    }
+}
+
+fn merge_sections(
+    key: &KeyPath,
+    base: &BTreeMap<String, Value>,
+    ours: &BTreeMap<String, Value>,
+    theirs: &BTreeMap<String, Value>,
+    conflicts: &mut Vec<Conflict>,
+) -> BTreeMap<String, Value> {
+    let mut names: Vec<_> = base
+        .keys()
+        .chain(ours.keys())
+        .chain(theirs.keys())
+        .collect();
+    names.sort();
+    names.dedup();
+    let mut merged = BTreeMap::new();
+    for name in names {
+        let key = key.join(name.as_str());
+        let (b, o, t) = (base.get(name), ours.get(name), theirs.get(name));
+        let value = match (name == "key").then(|| merge_keys(&key, b, o, t, conflicts)) {
+            Some(Some(value)) => Some(value),
+            _ => merge_values(&key, b, o, t, conflicts),
+        };
+        if let Some(value) = value {
+            merged.insert(name.clone(), value);
+        }
+    }
+    merged
+}
+
+/// The keyframes of a curve's `key` sequence with the value each was read
+/// from, or `None` if it is not one or has two keys at the same frame.
+fn keys(value: &Value) -> Option<Vec<(Key, &Value)>> {
+    let keys: Vec<Key> = from_value(value.clone()).ok()?;
+    let elements = (0..keys.len()).map(|i| value.get(&i.to_string()));
+    let keys: Vec<_> = keys
+        .into_iter()
+        .zip(elements.collect::<Option<Vec<_>>>()?)
+        .collect();
+    let mut frames: Vec<_> = keys.iter().map(|(x, _)| x.frame()).collect();
+    frames.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    frames.dedup();
+    (frames.len() == keys.len()).then_some(keys)
+}
+
+/// Merges keyframes by frame, keeping the text of the keys as written.
+/// Returns `None` to fall back to merging by index when any side is not a
+/// curve's `key` sequence.
+fn merge_keys(
+    curve: &KeyPath,
+    base: Option<&Value>,
+    ours: Option<&Value>,
+    theirs: Option<&Value>,
+    conflicts: &mut Vec<Conflict>,
+) -> Option<Value> {
+    let (base, ours, theirs) = (keys(base?)?, keys(ours?)?, keys(theirs?)?);
+    let mut frames: Vec<f32> = base
+        .iter()
+        .chain(&ours)
+        .chain(&theirs)
+        .map(|(x, _)| x.frame())
+        .collect();
+    frames.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    frames.dedup();
+
+    let mut merged = Vec::new();
+    for frame in frames {
+        let (b, o, t) = (at(&base, frame), at(&ours, frame), at(&theirs, frame));
+        let key = |x: Option<(Key, &Value)>| x.map(|(x, _)| x);
+        let value = if key(o) == key(t) || key(b) == key(t) {
+            o
+        } else if key(b) == key(o) {
+            t
+        } else {
+            conflicts.push(Conflict {
+                key: curve.clone(),
+                frame: Some(frame),
+                base: b.map(|(_, x)| x.clone()),
+                ours: o.map(|(_, x)| x.clone()),
+                theirs: t.map(|(_, x)| x.clone()),
+            });
+            o
+        };
+        merged.extend(value.map(|(_, x)| x.clone()));
+    }
+
+    let mut section = BTreeMap::new();
+    section.insert("length".to_string(), Value::from(merged.len().to_string()));
+    for (i, value) in merged.into_iter().enumerate() {
+        section.insert(i.to_string(), value);
+    }
+    Some(Value::Section(section))
+}
+
+fn at<'a>(keys: &[(Key, &'a Value)], frame: f32) -> Option<(Key, &'a Value)> {
+    keys.iter().find(|(x, _)| x.frame() == frame).copied()
+}
+
+#[test]
+fn merge_curves() {
+    let base = Value::parse(
+        "camera.fov=0.5
+camera.roll.key.0.data=(0,1)
+camera.roll.key.0.type=1
+camera.roll.key.1.data=(10,2)
+camera.roll.key.1.type=1
+camera.roll.key.length=2
+light.color=(1,1,1)
+",
+    )
+    .unwrap();
+    let mut ours = base.clone();
+    ours.insert("camera.fov", "0.6");
+    ours.insert("camera.roll.key.1.data", "(5,1.5)");
+    ours.insert("camera.roll.key.2.data", "(10,2)");
+    ours.insert("camera.roll.key.2.type", "1");
+    ours.insert("camera.roll.key.length", "3");
+    let mut theirs = base.clone();
+    theirs.insert("light.color", "(1,0.5,0.5)");
+    theirs.insert("camera.roll.key.2.data", "(20,3)");
+    theirs.insert("camera.roll.key.2.type", "1");
+    theirs.insert("camera.roll.key.length", "3");
+
+    let (merged, conflicts) = merge(&base, &ours, &theirs);
+    assert!(conflicts.is_empty());
+    assert_eq!(
+        crate::to_string(&merged).unwrap(),
+        "camera.fov=0.6
+camera.roll.key.0.data=(0,1)
+camera.roll.key.0.type=1
+camera.roll.key.1.data=(5,1.5)
+camera.roll.key.1.type=1
+camera.roll.key.2.data=(10,2)
+camera.roll.key.2.type=1
+camera.roll.key.3.data=(20,3)
+camera.roll.key.3.type=1
+camera.roll.key.length=4
+light.color=(1,0.5,0.5)
+"
+    );
+
+    theirs.insert("camera.fov", "0.7");
+    theirs.insert("camera.roll.key.0.data", "(0,1.25)");
+    let (merged, conflicts) = merge(&base, &ours, &theirs);
+    assert_eq!(
+        merged.get("camera.fov").and_then(Value::as_str),
+        Some("0.6")
+    );
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(
+        conflicts[0].to_string(),
+        "camera.fov: base 0.5, ours 0.6, theirs 0.7"
+    );
+
+    ours.insert("camera.roll.key.0.data", "(0,0.75)");
+    let (_, conflicts) = merge(&base, &ours, &theirs);
+    assert_eq!(
+        conflicts[1].to_string(),
+        "camera.roll.key @ 0: base {data=(0,1), type=1}, ours {data=(0,0.75), type=1}, \
+         theirs {data=(0,1.25), type=1}"
+    );
+}
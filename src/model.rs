@@ -0,0 +1,439 @@
+//! Typed model of the A3DA sections used by cameras and objects.
+//!
+//! Keys the model does not know are skipped when deserializing, so files with
+//! extra sections still load; `from_str_with_unknown` lists them.
+
+use serde::{de, ser, Deserialize, Deserializer, Serialize, Serializer};
+
+use std::convert::TryFrom;
+use std::fmt;
+
+/// A whole A3DA file, without its `#` header.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct A3da {
+    #[serde(rename = "_")]
+    pub metadata: Metadata,
+    pub play_control: PlayControl,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub camera_root: Vec<CameraRoot>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub object: Vec<Object>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub object_list: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub objhrc: Vec<ObjectHrc>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub objhrc_list: Vec<String>,
+}
+
+/// The `_` section written by the converter.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub converter: Version,
+    pub file_name: String,
+    pub property: Version,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Version {
+    pub version: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayControl {
+    pub begin: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub div: Option<u32>,
+    pub fps: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub offset: Option<f32>,
+    /// Length of the animation in frames.
+    pub size: f32,
+}
+
+/// An animated value, tagged by its `type` key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Curve {
+    /// Not animated; left at the default.
+    None {},
+    Static {
+        value: f32,
+    },
+    Linear {
+        key: Vec<Key>,
+        max: f32,
+    },
+    Hermite {
+        key: Vec<Key>,
+        max: f32,
+    },
+    Hold {
+        key: Vec<Key>,
+        max: f32,
+    },
+}
+
+impl Curve {
+    /// The keyframes of an animated curve, empty otherwise.
+    pub fn keys(&self) -> &[Key] {
+        match self {
+            Curve::Linear { key, .. } | Curve::Hermite { key, .. } | Curve::Hold { key, .. } => key,
+            Curve::None {} | Curve::Static { .. } => &[],
+        }
+    }
+
+    /// The value of the curve at `frame`, or `None` if it is not animated.
+    ///
+    /// Frames before the first key or after the last one hold that key's
+    /// value. `Null` keys have a value of `0`.
+    pub fn value_at(&self, frame: f32) -> Option<f32> {
+        let keys = match self {
+            Curve::None {} => return None,
+            Curve::Static { value } => return Some(*value),
+            Curve::Linear { key, .. } | Curve::Hermite { key, .. } | Curve::Hold { key, .. } => key,
+        };
+        let next = keys.iter().position(|x| x.frame() > frame);
+        let (k0, k1) = match next {
+            None => return keys.last().map(|x| x.value().unwrap_or(0.0)),
+            Some(0) => return Some(keys[0].value().unwrap_or(0.0)),
+            Some(i) => (keys[i - 1], keys[i]),
+        };
+        let (v0, v1) = (k0.value().unwrap_or(0.0), k1.value().unwrap_or(0.0));
+        let span = k1.frame() - k0.frame();
+        let t = (frame - k0.frame()) / span;
+        Some(match self {
+            Curve::Hold { .. } => v0,
+            Curve::Linear { .. } => v0 + (v1 - v0) * t,
+            _ => {
+                let (t2, t3) = (t * t, t * t * t);
+                let (_, out_tangent) = k0.tangents();
+                let (in_tangent, _) = k1.tangents();
+                v0 * (2.0 * t3 - 3.0 * t2 + 1.0)
+                    + v1 * (3.0 * t2 - 2.0 * t3)
+                    + (out_tangent * (t3 - 2.0 * t2 + t) + in_tangent * (t3 - t2)) * span
+            }
+        })
+    }
+}
+
+/// A keyframe of a curve, tagged by its `type` key. Its `data` is the frame
+/// followed by the value and tangents.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "RawKey", into = "RawKey")]
+pub enum Key {
+    /// A frame without a value, written as a bare `data=frame`.
+    Null(f32),
+    Linear(f32, f32),
+    Hermite(f32, f32, f32),
+    /// Hermite with separate incoming and outgoing tangents.
+    HermiteTangents(f32, f32, f32, f32),
+}
+
+impl Key {
+    pub fn frame(&self) -> f32 {
+        match *self {
+            Key::Null(frame)
+            | Key::Linear(frame, _)
+            | Key::Hermite(frame, ..)
+            | Key::HermiteTangents(frame, ..) => frame,
+        }
+    }
+
+    /// The value at this key, or `None` for `Null` keys.
+    pub fn value(&self) -> Option<f32> {
+        match *self {
+            Key::Null(_) => None,
+            Key::Linear(_, value)
+            | Key::Hermite(_, value, _)
+            | Key::HermiteTangents(_, value, ..) => Some(value),
+        }
+    }
+
+    /// The incoming and outgoing tangents in value per frame, which are flat
+    /// for keys without any.
+    pub fn tangents(&self) -> (f32, f32) {
+        match *self {
+            Key::Null(_) | Key::Linear(..) => (0.0, 0.0),
+            Key::Hermite(_, _, tangent) => (tangent, tangent),
+            Key::HermiteTangents(_, _, t1, t2) => (t1, t2),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct RawKey {
+    data: KeyData,
+    r#type: u8,
+}
+
+/// `data` is a bare number for `Null` keys and an inline tuple otherwise.
+enum KeyData {
+    Frame(f32),
+    Items(Vec<f32>),
+}
+
+impl TryFrom<RawKey> for Key {
+    type Error = String;
+
+    fn try_from(raw: RawKey) -> Result<Self, Self::Error> {
+        let items = match raw.data {
+            KeyData::Frame(frame) => vec![frame],
+            KeyData::Items(items) => items,
+        };
+        match (raw.r#type, &items[..]) {
+            (0, &[frame]) => Ok(Key::Null(frame)),
+            (1, &[frame, value]) => Ok(Key::Linear(frame, value)),
+            (2, &[frame, value, tangent]) => Ok(Key::Hermite(frame, value, tangent)),
+            (3, &[frame, value, t1, t2]) => Ok(Key::HermiteTangents(frame, value, t1, t2)),
+            (0..=3, _) => Err(format!(
+                "key type {} expects {} items, found {}",
+                raw.r#type,
+                raw.r#type + 1,
+                items.len()
+            )),
+            (r#type, _) => Err(format!("unknown key type {}", r#type)),
+        }
+    }
+}
+
+impl From<Key> for RawKey {
+    fn from(key: Key) -> Self {
+        let (r#type, data) = match key {
+            Key::Null(frame) => (0, KeyData::Frame(frame)),
+            Key::Linear(frame, value) => (1, KeyData::Items(vec![frame, value])),
+            Key::Hermite(frame, value, tangent) => (2, KeyData::Items(vec![frame, value, tangent])),
+            Key::HermiteTangents(frame, value, t1, t2) => {
+                (3, KeyData::Items(vec![frame, value, t1, t2]))
+            }
+        };
+        RawKey { data, r#type }
+    }
+}
+
+impl Serialize for KeyData {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use ser::SerializeTupleStruct;
+        match self {
+            KeyData::Frame(frame) => frame.serialize(serializer),
+            KeyData::Items(items) => {
+                let mut tuple = serializer.serialize_tuple_struct("KeyData", items.len())?;
+                for item in items {
+                    tuple.serialize_field(item)?;
+                }
+                tuple.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyData {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = KeyData;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a frame or a tuple of numbers")
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<KeyData, E> {
+                Ok(KeyData::Frame(v as f32))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<KeyData, E> {
+                Ok(KeyData::Frame(v as f32))
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<KeyData, E> {
+                Ok(KeyData::Frame(v as f32))
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<KeyData, A::Error> {
+                let mut items = Vec::new();
+                while let Some(item) = seq.next_element()? {
+                    items.push(item);
+                }
+                Ok(KeyData::Items(items))
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+/// One curve per axis.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Vec3 {
+    pub x: Curve,
+    pub y: Curve,
+    pub z: Curve,
+}
+
+/// Rotation, scale, translation and visibility of a node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Transform {
+    pub rot: Vec3,
+    pub scale: Vec3,
+    pub trans: Vec3,
+    pub visibility: Curve,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CameraRoot {
+    /// The point the camera looks at.
+    pub interest: Transform,
+    pub rot: Vec3,
+    pub scale: Vec3,
+    pub trans: Vec3,
+    pub view_point: ViewPoint,
+    pub visibility: Curve,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ViewPoint {
+    pub aspect: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera_aperture_h: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub camera_aperture_w: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub focal_length: Option<Curve>,
+    /// Field of view in radians.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fov: Option<Curve>,
+    #[serde(
+        serialize_with = "serialize_flag",
+        deserialize_with = "deserialize_flag"
+    )]
+    pub fov_is_horizontal: bool,
+    pub roll: Curve,
+    pub rot: Vec3,
+    pub scale: Vec3,
+    pub trans: Vec3,
+    pub visibility: Curve,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Object {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub morph: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub morph_offset: Option<f32>,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_name: Option<String>,
+    pub rot: Vec3,
+    pub scale: Vec3,
+    pub trans: Vec3,
+    pub uid_name: String,
+    pub visibility: Curve,
+}
+
+/// An object with a bone hierarchy.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObjectHrc {
+    pub name: String,
+    pub node: Vec<ObjectNode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_name: Option<String>,
+    pub uid_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ObjectNode {
+    pub name: String,
+    /// Index of the parent node, or `-1` for the root.
+    pub parent: i32,
+    pub rot: Vec3,
+    pub scale: Vec3,
+    pub trans: Vec3,
+    pub visibility: Curve,
+}
+
+/// Booleans are written as `0` and `1`.
+fn serialize_flag<S: Serializer>(v: &bool, serializer: S) -> Result<S::Ok, S::Error> {
+    u8::from(*v).serialize(serializer)
+}
+
+/// Reads flags written by `serialize_flag`, or booleans from formats that
+/// have them.
+fn deserialize_flag<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    struct Visitor;
+
+    impl<'de> de::Visitor<'de> for Visitor {
+        type Value = bool;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("0, 1 or a boolean")
+        }
+
+        fn visit_bool<E: de::Error>(self, v: bool) -> Result<bool, E> {
+            Ok(v)
+        }
+
+        fn visit_u64<E: de::Error>(self, v: u64) -> Result<bool, E> {
+            match v {
+                0 => Ok(false),
+                1 => Ok(true),
+                _ => Err(E::invalid_value(de::Unexpected::Unsigned(v), &self)),
+            }
+        }
+
+        fn visit_i64<E: de::Error>(self, v: i64) -> Result<bool, E> {
+            match u64::try_from(v) {
+                Ok(v) => self.visit_u64(v),
+                Err(_) => Err(E::invalid_value(de::Unexpected::Signed(v), &self)),
+            }
+        }
+    }
+
+    deserializer.deserialize_any(Visitor)
+}
+
+#[test]
+fn round_trip_model() {
+    use chrono::NaiveDateTime;
+
+    use crate::{from_str, to_string_with, SerializerOptions, Timestamp, DATE_FMT};
+
+    let input = std::fs::read_to_string("assets/CAMPV001_BASE.a3da").unwrap();
+    let a3da: A3da = from_str(&input).unwrap();
+    assert_eq!(a3da.metadata.converter.version, 20050823);
+    assert_eq!(a3da.play_control.size, 9301.0);
+    let view_point = &a3da.camera_root[0].view_point;
+    assert_eq!(view_point.fov, Some(Curve::Static { value: 0.93616 }));
+    assert_eq!(view_point.roll.keys()[24], Key::Null(5945.0));
+    assert_eq!(
+        a3da.camera_root[0].interest.trans.x.keys()[1],
+        Key::Hermite(738.0, -0.522281, 3.31402e-6)
+    );
+
+    let date = NaiveDateTime::parse_from_str("Wed Dec 30 15:32:02 2009", DATE_FMT).unwrap();
+    let options = SerializerOptions::new()
+        .header(true)
+        .timestamp(Timestamp::Fixed(date))
+        .float_precision(Some(6));
+    assert_eq!(to_string_with(&a3da, &options).unwrap(), input);
+
+    let roll = &view_point.roll;
+    assert_eq!(roll.value_at(-10.0), roll.keys()[0].value());
+    assert_eq!(roll.value_at(1e9), roll.keys()[47].value());
+    let hermite = Curve::Hermite {
+        key: vec![Key::Hermite(0.0, 0.0, 0.0), Key::Hermite(10.0, 1.0, 0.0)],
+        max: 10.0,
+    };
+    assert_eq!(hermite.value_at(5.0), Some(0.5));
+    assert_eq!(hermite.value_at(10.0), Some(1.0));
+    let linear = Curve::Linear {
+        key: vec![Key::Linear(0.0, 0.0), Key::Linear(10.0, 1.0)],
+        max: 10.0,
+    };
+    assert_eq!(linear.value_at(2.5), Some(0.25));
+    assert_eq!(Curve::None {}.value_at(0.0), None);
+
+    let err = from_str::<Key>("data=(1,2,3)\ntype=1\n").unwrap_err();
+    assert!(err
+        .to_string()
+        .contains("key type 1 expects 2 items, found 3"));
+}
@@ -0,0 +1,284 @@
+use serde::de::DeserializeOwned;
+
+use std::io;
+
+use crate::de::from_scalar;
+use crate::encoding::{decode, TextEncoding};
+use crate::error::DeserializeError;
+use crate::key::KeyPath;
+
+type Result<T> = ::std::result::Result<T, DeserializeError>;
+
+/// Splits a line into its key and value at the first `=`, so values may
+/// themselves contain `=`.
+///
+/// Whitespace around the line, the key, each key segment and the value is not
+/// significant and is dropped; whitespace inside a value is kept. Lines with an
+/// empty key are malformed.
+pub(crate) fn split_line(line: &str) -> Option<(&str, &str)> {
+    let (lhs, rhs) = line.split_once('=')?;
+    let lhs = lhs.trim_end();
+    if lhs.is_empty() {
+        return None;
+    }
+    Some((lhs, rhs.trim_start()))
+}
+
+/// One line of A3DA text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Token<'a> {
+    /// One of the `#` lines at the top of the file, without the `#`.
+    Header(&'a str),
+    Pair(&'a str, &'a str),
+    /// A blank line or a comment after the header.
+    Skip,
+}
+
+/// Classifies lines one at a time. `Lines`, which the deserializer indexes,
+/// and `Reader` both feed every line through one of these, so they agree on
+/// line numbers, where the header ends and which lines are malformed.
+#[derive(Debug, Default)]
+pub(crate) struct Tokenizer {
+    line: usize,
+    after_header: bool,
+}
+
+impl Tokenizer {
+    /// Reads the next line, which may still end in its line terminator.
+    pub(crate) fn next<'a>(&mut self, text: &'a str) -> Result<Token<'a>> {
+        self.line += 1;
+        let text = text.trim();
+        if let Some(header) = text.strip_prefix('#') {
+            return Ok(match self.after_header {
+                false => Token::Header(header),
+                true => Token::Skip,
+            });
+        }
+        self.after_header = true;
+        if text.is_empty() {
+            return Ok(Token::Skip);
+        }
+        let (key, value) = split_line(text).ok_or(DeserializeError::MalformedLine(self.line))?;
+        Ok(Token::Pair(key, value))
+    }
+
+    /// The number of the line last read, from 1.
+    pub(crate) fn line(&self) -> usize {
+        self.line
+    }
+}
+
+/// The `key=value` lines of a string as `(line, key, value)` slices of it.
+/// This is what the deserializer indexes. Lines end at `\n` like those of
+/// `Reader`.
+pub(crate) struct Lines<'de> {
+    lines: std::str::SplitInclusive<'de, char>,
+    tokenizer: Tokenizer,
+}
+
+impl<'de> Lines<'de> {
+    pub(crate) fn new(input: &'de str) -> Self {
+        Self {
+            lines: input.split_inclusive('\n'),
+            tokenizer: Tokenizer::default(),
+        }
+    }
+}
+
+impl<'de> Iterator for Lines<'de> {
+    type Item = Result<(usize, &'de str, &'de str)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for line in &mut self.lines {
+            match self.tokenizer.next(line) {
+                Ok(Token::Pair(key, value)) => {
+                    return Some(Ok((self.tokenizer.line(), key, value)))
+                }
+                Ok(_) => {}
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        None
+    }
+}
+
+/// The value half of a `key=value` line, exactly as written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawValue(String);
+
+impl RawValue {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_string(self) -> String {
+        self.0
+    }
+
+    /// Reads the value like a field of type `T` would be, so numbers, booleans
+    /// and `(…)` tuples are all accepted.
+    pub fn deserialize<T: DeserializeOwned>(&self) -> Result<T> {
+        from_scalar(&self.0)
+    }
+}
+
+/// One `key=value` line, with the line number it was read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    pub key: KeyPath,
+    pub value: RawValue,
+    pub line: usize,
+}
+
+/// Reads `key=value` lines one at a time, without building a tree.
+///
+/// Only the current line is held in memory, so this suits filtering or
+/// scanning files of any size. Leading `#` lines are kept as the header;
+/// blank lines and later comments are skipped.
+pub struct Reader<R> {
+    reader: R,
+    encoding: Option<TextEncoding>,
+    buf: Vec<u8>,
+    tokenizer: Tokenizer,
+    header: Vec<String>,
+    done: bool,
+}
+
+impl<R: io::BufRead> Reader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            encoding: None,
+            buf: Vec::new(),
+            tokenizer: Tokenizer::default(),
+            header: Vec::new(),
+            done: false,
+        }
+    }
+
+    /// Encoding of the input. By default each line is read as UTF-8 if it is
+    /// valid UTF-8 and as Shift-JIS otherwise.
+    pub fn encoding(mut self, encoding: TextEncoding) -> Self {
+        self.encoding = Some(encoding);
+        self
+    }
+
+    /// The leading `#` lines read so far, without the `#`. The header is
+    /// complete once the first event has been returned.
+    pub fn header(&self) -> &[String] {
+        &self.header
+    }
+
+    fn read_event(&mut self) -> Result<Option<Event>> {
+        loop {
+            self.buf.clear();
+            if self.reader.read_until(b'\n', &mut self.buf)? == 0 {
+                return Ok(None);
+            }
+            let text = decode(&self.buf, self.encoding)?;
+            let (key, value) = match self.tokenizer.next(&text)? {
+                Token::Pair(key, value) => (key, value),
+                Token::Header(header) => {
+                    self.header.push(header.to_string());
+                    continue;
+                }
+                Token::Skip => continue,
+            };
+            return Ok(Some(Event {
+                key: KeyPath::parse(key)?,
+                value: RawValue(value.to_string()),
+                line: self.tokenizer.line(),
+            }));
+        }
+    }
+}
+
+impl<R: io::BufRead> Iterator for Reader<R> {
+    type Item = Result<Event>;
+
+    /// Stops after the first error, since the line it happened on may not
+    /// have been consumed.
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let event = self.read_event().transpose();
+        if !matches!(event, Some(Ok(_))) {
+            self.done = true;
+        }
+        event
+    }
+}
+
+#[test]
+fn read_events() {
+    let input = "#A3DA__________
+#Wed Dec 30 15:32:02 2009
+
+camera_root.0.view_point.fov.value = 0.93616
+# a comment
+camera_root.0.view_point.trans.x.key.0.data=(0,1,2)
+play_control.size=9301
+";
+    let mut reader = Reader::new(input.as_bytes());
+    let first = reader.next().unwrap().unwrap();
+    assert_eq!(
+        reader.header(),
+        ["A3DA__________", "Wed Dec 30 15:32:02 2009"]
+    );
+    assert_eq!(first.key.to_string(), "camera_root.0.view_point.fov.value");
+    assert_eq!(first.value.as_str(), "0.93616");
+    assert_eq!(first.line, 4);
+
+    let events: Vec<_> = reader.collect::<Result<_>>().unwrap();
+    assert_eq!(events[0].line, 6);
+    assert_eq!(
+        events[0].value.deserialize::<(u32, f32, f32)>().unwrap(),
+        (0, 1.0, 2.0)
+    );
+    assert_eq!(events[1].value.deserialize::<u32>().unwrap(), 9301);
+    assert_eq!(events.len(), 2);
+
+    let mut reader = Reader::new("a=1\nnot a pair\nb=2\n".as_bytes());
+    assert!(reader.next().unwrap().is_ok());
+    assert!(matches!(
+        reader.next(),
+        Some(Err(DeserializeError::MalformedLine(2)))
+    ));
+    assert!(reader.next().is_none());
+}
+
+#[test]
+fn lines_match_reader() {
+    let input = "#A3DA__________\r\n#date\r\n\r\n# later\r\n a . b = 1 \r\nc=(0, 1)\r\n#x\nd=";
+    let lines: Vec<_> = Lines::new(input).collect::<Result<_>>().unwrap();
+    let mut reader = Reader::new(input.as_bytes());
+    let events: Vec<_> = (&mut reader).collect::<Result<_>>().unwrap();
+    assert_eq!(reader.header(), ["A3DA__________", "date"]);
+    assert_eq!(lines, [(5, "a . b", "1"), (6, "c", "(0, 1)"), (8, "d", "")]);
+    let events: Vec<_> = events
+        .iter()
+        .map(|x| (x.line, x.key.to_string(), x.value.as_str().to_string()))
+        .collect();
+    let lines: Vec<_> = lines
+        .iter()
+        .map(|&(line, key, value)| {
+            (
+                line,
+                KeyPath::parse(key).unwrap().to_string(),
+                value.to_string(),
+            )
+        })
+        .collect();
+    assert_eq!(events, lines);
+
+    let input = "a=1\r\n\r\nbad\r\n";
+    assert!(matches!(
+        Lines::new(input).nth(1),
+        Some(Err(DeserializeError::MalformedLine(3)))
+    ));
+    assert!(matches!(
+        Reader::new(input.as_bytes()).nth(1),
+        Some(Err(DeserializeError::MalformedLine(3)))
+    ));
+}
@@ -1,92 +1,391 @@
+use chrono::{Local, NaiveDateTime};
 use serde::{ser, Serialize};
-use slab_tree::*;
 
+use std::io;
+
+use crate::encoding::{encode, TextEncoding};
 use crate::error::*;
+use crate::value::Value;
+use crate::DATE_FMT;
 
 type Result<T> = std::result::Result<T, SerializeError>;
 
-struct Serializer {
-    tree: Tree<String>,
-    cur: Option<NodeId>,
+/// Line terminator written after the header and every `key=value` line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineEnding {
+    Lf,
+    /// Used by files exported from the original Windows tools.
+    CrLf,
+}
+
+impl LineEnding {
+    fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+        }
+    }
+}
+
+/// Date written on the second line of the `#A3DA` header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Timestamp {
+    Now,
+    Fixed(NaiveDateTime),
+}
+
+/// Order in which `key=value` lines are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyOrder {
+    /// Sorted lexically by key, as the original tools write them. Lines have
+    /// to be buffered until the whole value has been visited.
+    Canonical,
+    /// Written as soon as serde visits them, without any buffering.
+    Declaration,
+}
+
+/// Controls the textual style of serialized output.
+///
+/// The defaults match `to_string`: no header, `\n` line endings, canonical
+/// key order and the shortest float representation that round-trips.
+#[derive(Debug, Clone)]
+pub struct SerializerOptions {
+    header: bool,
+    timestamp: Timestamp,
+    line_ending: LineEnding,
+    key_order: KeyOrder,
+    float_precision: Option<usize>,
+    encoding: TextEncoding,
 }
 
-impl Serializer {
-    fn new() -> Self {
+impl SerializerOptions {
+    pub fn new() -> Self {
         Self::default()
     }
+
+    /// Whether to start the output with the `#A3DA` signature and date lines.
+    pub fn header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: Timestamp) -> Self {
+        self.timestamp = timestamp;
+        self
+    }
+
+    pub fn line_ending(mut self, line_ending: LineEnding) -> Self {
+        self.line_ending = line_ending;
+        self
+    }
+
+    pub fn key_order(mut self, key_order: KeyOrder) -> Self {
+        self.key_order = key_order;
+        self
+    }
+
+    /// Formats floats like C's `%g` with `precision` significant digits,
+    /// e.g. `Some(6)` gives `1.77778` and `3.31402e-006` like the original
+    /// files. `None` writes the shortest representation that round-trips.
+    pub fn float_precision(mut self, precision: Option<usize>) -> Self {
+        self.float_precision = precision.map(|x| x.max(1));
+        self
+    }
+
+    /// Encoding of the bytes written by `to_writer_with`. Strings returned by
+    /// `to_string_with` are always UTF-8.
+    pub fn encoding(mut self, encoding: TextEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
 }
 
-impl Default for Serializer {
+impl Default for SerializerOptions {
     fn default() -> Self {
-        let tree = TreeBuilder::new().with_root("root".to_string()).build();
-        Self { tree, cur: None }
+        Self {
+            header: false,
+            timestamp: Timestamp::Now,
+            line_ending: LineEnding::Lf,
+            key_order: KeyOrder::Canonical,
+            float_precision: None,
+            encoding: TextEncoding::Utf8,
+        }
     }
 }
 
-struct SubSerializer<'a> {
-    inner: &'a mut Serializer,
+/// Writes `key=value` lines without building a tree of the value first.
+///
+/// The dotted key of the value being serialized is kept as a prefix stack:
+/// entering a field or element pushes a segment onto `key`, leaving it
+/// truncates back. In declaration order each line goes straight to the
+/// writer. In canonical order, the default, every line is appended to a
+/// single buffer which is sorted by key and written out by `end`, since serde
+/// visits fields in declaration order, so the whole output is held in memory.
+/// `to_value` sets `value`, which collects the leaves instead of writing them.
+struct Serializer<'o, W> {
+    writer: W,
+    options: &'o SerializerOptions,
+    key: String,
+    buf: String,
+    lines: Vec<Line>,
+    tuple: Option<String>,
+    value: Option<Value>,
+}
+
+/// Byte offsets of one `key=value` line inside `Serializer::buf`.
+struct Line {
+    start: usize,
+    eq: usize,
+    end: usize,
+}
+
+impl<'o, W: io::Write> Serializer<'o, W> {
+    fn new(writer: W, options: &'o SerializerOptions) -> Self {
+        Self {
+            writer,
+            options,
+            key: String::new(),
+            buf: String::new(),
+            lines: Vec::new(),
+            tuple: None,
+            value: None,
+        }
+    }
+
+    fn begin(&mut self) -> Result<()> {
+        if !self.options.header {
+            return Ok(());
+        }
+        let date = match &self.options.timestamp {
+            Timestamp::Now => Local::now().naive_local(),
+            Timestamp::Fixed(date) => *date,
+        };
+        let eol = self.options.line_ending.as_str();
+        write!(
+            self.writer,
+            "#A3DA__________{}#{}{}",
+            eol,
+            date.format(DATE_FMT),
+            eol
+        )?;
+        Ok(())
+    }
+
+    /// Appends `segment` to the current key, returning the length to truncate
+    /// back to once the value under it has been serialized.
+    fn push_key(&mut self, segment: &str) -> usize {
+        let len = self.key.len();
+        if len > 0 {
+            self.key.push('.');
+        }
+        self.key.push_str(segment);
+        len
+    }
+
+    fn pop_key(&mut self, len: usize) {
+        self.key.truncate(len);
+    }
+
+    fn emit(&mut self, value: &str) -> Result<()> {
+        if let Some(tuple) = &mut self.tuple {
+            tuple.push_str(value);
+            return Ok(());
+        }
+        if self.key.is_empty() {
+            return Err(SerializeError::MissingKey);
+        }
+        check_key(&self.key)?;
+        check_value(value)?;
+        if let Some(tree) = &mut self.value {
+            tree.insert(&self.key, value);
+            return Ok(());
+        }
+        let start = self.buf.len();
+        self.buf.push_str(&self.key);
+        let eq = self.buf.len();
+        self.buf.push('=');
+        self.buf.push_str(value);
+        self.buf.push_str(self.options.line_ending.as_str());
+        match self.options.key_order {
+            KeyOrder::Canonical => self.lines.push(Line {
+                start,
+                eq,
+                end: self.buf.len(),
+            }),
+            KeyOrder::Declaration => {
+                self.writer
+                    .write_all(&encode(&self.buf, self.options.encoding)?)?;
+                self.buf.clear();
+            }
+        }
+        Ok(())
+    }
+
+    /// Rejects a section or sequence inside a tuple item, whose leaves would
+    /// run together into one item.
+    fn check_compound(&self) -> Result<()> {
+        match self.tuple {
+            Some(_) => Err(ser::Error::custom(
+                "tuple items must be single values, not sections or sequences",
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn emit_float(&mut self, v: f64) -> Result<()> {
+        match self.options.float_precision {
+            Some(precision) => self.emit(&format_float(v, precision)),
+            None => self.emit(&v.to_string()),
+        }
+    }
+
+    fn end(mut self) -> Result<W> {
+        let buf = &self.buf;
+        self.lines
+            .sort_by(|a, b| buf[a.start..a.eq].cmp(&buf[b.start..b.eq]));
+        for line in &self.lines {
+            let line = &buf[line.start..line.end];
+            self.writer
+                .write_all(&encode(line, self.options.encoding)?)?;
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Rejects keys that would be read back as a different key or as a comment.
+pub(crate) fn check_key(key: &str) -> Result<()> {
+    let malformed = key.starts_with('#')
+        || key.contains(['=', '\n', '\r'])
+        || key.split('.').any(|x| x.is_empty() || x.trim() != x);
+    if malformed {
+        return Err(SerializeError::InvalidKey(key.to_string()));
+    }
+    Ok(())
+}
+
+/// Rejects values that would be cut short or trimmed when read back. The
+/// format has no escapes, so these cannot be written at all.
+pub(crate) fn check_value(value: &str) -> Result<()> {
+    if value.contains(['\n', '\r']) || value.trim() != value {
+        return Err(SerializeError::InvalidValue(value.to_string()));
+    }
+    Ok(())
+}
+
+/// Rejects tuple items that would split or nest the tuple when read back.
+pub(crate) fn check_item(item: &str) -> Result<()> {
+    if item.contains([',', '(', ')']) {
+        return Err(SerializeError::InvalidValue(item.to_string()));
+    }
+    Ok(())
+}
+
+/// Formats `v` like C's `%g`, with the three digit exponent MSVC prints.
+pub(crate) fn format_float(v: f64, precision: usize) -> String {
+    if v == 0.0 || !v.is_finite() {
+        return v.to_string();
+    }
+    let sci = format!("{:.*e}", precision - 1, v);
+    let (mantissa, exp) = sci.split_at(sci.find('e').unwrap());
+    let exp: i32 = exp[1..].parse().unwrap();
+    if exp < -4 || exp >= precision as i32 {
+        let sign = if exp < 0 { '-' } else { '+' };
+        format!("{}e{}{:03}", trim_zeros(mantissa), sign, exp.abs())
+    } else {
+        let decimals = (precision as i32 - 1 - exp) as usize;
+        trim_zeros(&format!("{:.*}", decimals, v)).to_string()
+    }
+}
+
+fn trim_zeros(v: &str) -> &str {
+    if v.contains('.') {
+        v.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        v
+    }
+}
+
+struct SubSerializer<'a, 'o, W> {
+    inner: &'a mut Serializer<'o, W>,
     count: usize,
     buf: String,
-    previous_root: Option<NodeId>,
 }
 
-impl<'a> SubSerializer<'a> {
-    fn new(inner: &'a mut Serializer) -> SubSerializer<'a> {
+impl<'a, 'o, W> SubSerializer<'a, 'o, W> {
+    fn new(inner: &'a mut Serializer<'o, W>) -> Self {
         Self {
             inner,
             count: 0,
             buf: String::new(),
-            previous_root: None,
         }
     }
 }
 
-pub fn to_string<T: ?Sized>(value: &T) -> Result<String>
+pub fn to_string<T>(value: &T) -> Result<String>
 where
-    T: Serialize,
+    T: Serialize + ?Sized,
 {
-    let mut serializer = Serializer::new();
+    to_string_with(value, &SerializerOptions::default())
+}
+
+pub fn to_writer<W, T>(writer: W, value: &T) -> Result<()>
+where
+    W: io::Write,
+    T: Serialize + ?Sized,
+{
+    to_writer_with(writer, value, &SerializerOptions::default())
+}
+
+pub fn to_string_with<T>(value: &T, options: &SerializerOptions) -> Result<String>
+where
+    T: Serialize + ?Sized,
+{
+    let options = options.clone().encoding(TextEncoding::Utf8);
+    let mut serializer = Serializer::new(Vec::new(), &options);
+    serializer.begin()?;
     value.serialize(&mut serializer)?;
-    Ok(write_tree(&serializer.tree))
+    let bytes = serializer.end()?;
+    String::from_utf8(bytes).map_err(ser::Error::custom)
 }
 
-pub fn to_writer<W: std::io::Write, T: ?Sized>(writer: W, value: &T) -> Result<()>
+pub fn to_writer_with<W, T>(writer: W, value: &T, options: &SerializerOptions) -> Result<()>
 where
-    T: Serialize,
+    W: io::Write,
+    T: Serialize + ?Sized,
 {
-    let mut serializer = Serializer::new();
+    let mut serializer = Serializer::new(writer, options);
+    serializer.begin()?;
     value.serialize(&mut serializer)?;
-    write_to_writer_inner(writer, &serializer.tree);
+    serializer.end()?;
     Ok(())
 }
 
-impl Serializer {
-    fn get_cur_mut(&mut self) -> Option<NodeMut<'_, String>> {
-        self.cur.and_then(move |x| self.tree.get_mut(x))
-    }
-
-    fn get_mut(&mut self) -> NodeMut<'_, String> {
-        if let Some(cur) = self.cur {
-            self.get_cur_mut().unwrap()
-        } else {
-            //Root is guarranteed to exist
-            self.tree.root_mut().unwrap()
-        }
-    }
+/// Serializes `value` into a key tree rather than text, so it can be edited by
+/// key and handed to `from_value`.
+pub fn to_value<T>(value: &T) -> Result<Value>
+where
+    T: Serialize + ?Sized,
+{
+    let options = SerializerOptions::default();
+    let mut serializer = Serializer::new(io::sink(), &options);
+    serializer.value = Some(Value::default());
+    value.serialize(&mut serializer)?;
+    Ok(serializer.value.unwrap_or_default())
 }
 
-impl<'a> ser::Serializer for &'a mut Serializer {
+impl<'a, 'o, W: io::Write> ser::Serializer for &'a mut Serializer<'o, W> {
     type Ok = ();
 
     type Error = SerializeError;
 
-    type SerializeSeq = SubSerializer<'a>;
-    type SerializeTuple = SubSerializer<'a>;
-    type SerializeTupleStruct = SubSerializer<'a>;
-    type SerializeTupleVariant = SubSerializer<'a>;
-    type SerializeMap = SubSerializer<'a>;
-    type SerializeStruct = SubSerializer<'a>;
-    type SerializeStructVariant = SubSerializer<'a>;
+    type SerializeSeq = SubSerializer<'a, 'o, W>;
+    type SerializeTuple = SubSerializer<'a, 'o, W>;
+    type SerializeTupleStruct = SubSerializer<'a, 'o, W>;
+    type SerializeTupleVariant = SubSerializer<'a, 'o, W>;
+    type SerializeMap = SubSerializer<'a, 'o, W>;
+    type SerializeStruct = SubSerializer<'a, 'o, W>;
+    type SerializeStructVariant = SubSerializer<'a, 'o, W>;
 
     fn serialize_i8(self, v: i8) -> Result<()> {
         self.serialize_i64(i64::from(v))
@@ -103,8 +402,7 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     // Not particularly efficient but this is example code anyway. A more
     // performant approach would be to use the `itoa` crate.
     fn serialize_i64(self, v: i64) -> Result<()> {
-        self.get_mut().append(v.to_string());
-        Ok(())
+        self.emit(&v.to_string())
     }
 
     fn serialize_u8(self, v: u8) -> Result<()> {
@@ -120,169 +418,184 @@ impl<'a> ser::Serializer for &'a mut Serializer {
     }
 
     fn serialize_u64(self, v: u64) -> Result<()> {
-        self.get_mut().append(v.to_string());
-        Ok(())
+        self.emit(&v.to_string())
     }
 
     fn serialize_f32(self, v: f32) -> Result<()> {
-        self.serialize_f64(f64::from(v))
+        match self.options.float_precision {
+            Some(_) => self.emit_float(f64::from(v)),
+            None => self.emit(&v.to_string()),
+        }
     }
 
     fn serialize_f64(self, v: f64) -> Result<()> {
-        self.get_mut().append(v.to_string());
-        Ok(())
+        self.emit_float(v)
     }
 
     fn serialize_bool(self, v: bool) -> Result<Self::Ok> {
-        self.get_mut().append(v.to_string());
-        Ok(())
+        self.emit(&v.to_string())
     }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok> {
-        self.get_mut().append(v.to_string());
-        Ok(())
+        self.emit(&v.to_string())
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok> {
-        self.get_mut().append(v.to_string());
-        Ok(())
+        self.emit(v)
     }
 
-    fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok> {
-        todo!()
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok> {
+        Err(ser::Error::custom(
+            "byte arrays cannot be written as A3DA values",
+        ))
     }
 
     fn serialize_none(self) -> Result<Self::Ok> {
         Ok(())
     }
 
-    fn serialize_some<T: ?Sized>(self, value: &T) -> Result<Self::Ok>
+    fn serialize_some<T>(self, value: &T) -> Result<Self::Ok>
     where
-        T: Serialize,
+        T: Serialize + ?Sized,
     {
         value.serialize(self)
     }
 
+    /// Units carry no data, so like `None` they write no line.
     fn serialize_unit(self) -> Result<Self::Ok> {
-        todo!()
+        Ok(())
     }
 
-    fn serialize_unit_struct(self, name: &'static str) -> Result<Self::Ok> {
-        todo!()
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok> {
+        Ok(())
     }
 
     fn serialize_unit_variant(
         self,
-        name: &'static str,
+        _name: &'static str,
         variant_index: u32,
-        variant: &'static str,
+        _variant: &'static str,
     ) -> Result<Self::Ok> {
         variant_index.serialize(self)
     }
 
-    fn serialize_newtype_struct<T: ?Sized>(self, name: &'static str, value: &T) -> Result<Self::Ok>
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<Self::Ok>
     where
-        T: Serialize,
+        T: Serialize + ?Sized,
     {
         value.serialize(self)
     }
 
-    fn serialize_newtype_variant<T: ?Sized>(
+    fn serialize_newtype_variant<T>(
         self,
-        name: &'static str,
+        _name: &'static str,
         variant_index: u32,
-        variant: &'static str,
+        _variant: &'static str,
         value: &T,
     ) -> Result<Self::Ok>
     where
-        T: Serialize,
+        T: Serialize + ?Sized,
     {
-        todo!()
+        self.check_compound()?;
+        let len = self.push_key("type");
+        self.emit(&variant_index.to_string())?;
+        self.pop_key(len);
+        let len = self.push_key("value");
+        value.serialize(&mut *self)?;
+        self.pop_key(len);
+        Ok(())
     }
 
-    fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq> {
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        self.check_compound()?;
         Ok(SubSerializer::new(self))
     }
 
-    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple> {
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        self.check_compound()?;
         Ok(SubSerializer::new(self))
     }
 
     fn serialize_tuple_struct(
         self,
-        name: &'static str,
-        len: usize,
+        _name: &'static str,
+        _len: usize,
     ) -> Result<Self::SerializeTupleStruct> {
+        self.check_compound()?;
         Ok(SubSerializer::new(self))
     }
 
     fn serialize_tuple_variant(
         self,
-        name: &'static str,
+        _name: &'static str,
         variant_index: u32,
-        variant: &'static str,
-        len: usize,
+        _variant: &'static str,
+        _len: usize,
     ) -> Result<Self::SerializeTupleVariant> {
-        let mut root = self.get_mut();
-        let mut node = root.append("type".to_string());
-        node.append(variant_index.to_string());
+        self.check_compound()?;
+        let len = self.push_key("type");
+        self.emit(&variant_index.to_string())?;
+        self.pop_key(len);
         Ok(SubSerializer::new(self))
     }
 
-    fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap> {
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        self.check_compound()?;
         Ok(SubSerializer::new(self))
     }
 
-    fn serialize_struct(self, name: &'static str, len: usize) -> Result<Self::SerializeStruct> {
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        self.check_compound()?;
         Ok(SubSerializer::new(self))
     }
 
     fn serialize_struct_variant(
         self,
-        name: &'static str,
+        _name: &'static str,
         variant_index: u32,
-        variant: &'static str,
-        len: usize,
+        _variant: &'static str,
+        _len: usize,
     ) -> Result<Self::SerializeStructVariant> {
+        self.check_compound()?;
+        let len = self.push_key("type");
+        self.emit(&variant_index.to_string())?;
+        self.pop_key(len);
         Ok(SubSerializer::new(self))
     }
 }
 
-impl<'a> ser::SerializeSeq for SubSerializer<'a> {
+impl<'a, 'o, W: io::Write> ser::SerializeSeq for SubSerializer<'a, 'o, W> {
     type Ok = ();
 
     type Error = SerializeError;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: Serialize + ?Sized,
     {
-        let prev = self.inner.cur;
-        let mut root = self.inner.get_mut();
-        let node = root.append(self.count.to_string());
-        self.inner.cur = Some(node.node_id());
+        let len = self.inner.push_key(&self.count.to_string());
         value.serialize(&mut *self.inner)?;
-        self.inner.cur = prev;
+        self.inner.pop_key(len);
         self.count += 1;
         Ok(())
     }
 
     fn end(self) -> Result<Self::Ok> {
-        let mut root = self.inner.get_mut();
-        let mut node = root.append("length".to_string());
-        node.append(self.count.to_string());
+        let len = self.inner.push_key("length");
+        self.inner.emit(&self.count.to_string())?;
+        self.inner.pop_key(len);
         Ok(())
     }
 }
 
-impl<'a> ser::SerializeTuple for SubSerializer<'a> {
+impl<'a, 'o, W: io::Write> ser::SerializeTuple for SubSerializer<'a, 'o, W> {
     type Ok = ();
 
     type Error = SerializeError;
 
-    fn serialize_element<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_element<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: Serialize + ?Sized,
     {
         <Self as ser::SerializeSeq>::serialize_element(self, value)
     }
@@ -292,22 +605,18 @@ impl<'a> ser::SerializeTuple for SubSerializer<'a> {
     }
 }
 
-impl<'a> ser::SerializeStruct for SubSerializer<'a> {
+impl<'a, 'o, W: io::Write> ser::SerializeStruct for SubSerializer<'a, 'o, W> {
     type Ok = ();
 
     type Error = SerializeError;
 
-    fn serialize_field<T: ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: Serialize + ?Sized,
     {
-        let mut root = self.inner.get_mut();
-        let node = root.append(key.to_string()).node_id();
-
-        let prev = self.inner.cur;
-        self.inner.cur = Some(node);
+        let len = self.inner.push_key(key);
         value.serialize(&mut *self.inner)?;
-        self.inner.cur = prev;
+        self.inner.pop_key(len);
         Ok(())
     }
 
@@ -316,28 +625,32 @@ impl<'a> ser::SerializeStruct for SubSerializer<'a> {
     }
 }
 
-//TODO: is this correct behaviour and is it valid to impl for this format?
-impl<'a> ser::SerializeMap for SubSerializer<'a> {
+/// Map keys become a segment of the key path, so `{"a": {"b": 1}}` under
+/// `section` is written as `section.a.b=1`.
+impl<'a, 'o, W: io::Write> ser::SerializeMap for SubSerializer<'a, 'o, W> {
     type Ok = ();
 
     type Error = SerializeError;
 
-    fn serialize_key<T: ?Sized>(&mut self, key: &T) -> Result<()>
+    fn serialize_key<T>(&mut self, key: &T) -> Result<()>
     where
-        T: Serialize,
+        T: Serialize + ?Sized,
     {
-        self.previous_root = self.inner.cur;
-        let mut root = self.inner.get_mut();
-        key.serialize(&mut *self.inner)
+        let key = key.serialize(MapKeySerializer)?;
+        if key.is_empty() || key.contains(['.', '=']) {
+            return Err(SerializeError::InvalidMapKey(key));
+        }
+        self.buf = key;
+        Ok(())
     }
 
-    fn serialize_value<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_value<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: Serialize + ?Sized,
     {
-        let mut root = self.inner.get_mut();
+        let len = self.inner.push_key(&self.buf);
         value.serialize(&mut *self.inner)?;
-        self.inner.cur = self.previous_root;
+        self.inner.pop_key(len);
         Ok(())
     }
 
@@ -346,133 +659,259 @@ impl<'a> ser::SerializeMap for SubSerializer<'a> {
     }
 }
 
-impl<'a> ser::SerializeTupleStruct for SubSerializer<'a> {
-    type Ok = ();
+/// Turns a map key into a single key segment. Only strings and integers are
+/// accepted.
+struct MapKeySerializer;
+
+impl MapKeySerializer {
+    fn unsupported<T>(self) -> Result<T> {
+        Err(ser::Error::custom("map keys must be strings or integers"))
+    }
+}
+
+impl ser::Serializer for MapKeySerializer {
+    type Ok = String;
 
     type Error = SerializeError;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    type SerializeSeq = ser::Impossible<String, SerializeError>;
+    type SerializeTuple = ser::Impossible<String, SerializeError>;
+    type SerializeTupleStruct = ser::Impossible<String, SerializeError>;
+    type SerializeTupleVariant = ser::Impossible<String, SerializeError>;
+    type SerializeMap = ser::Impossible<String, SerializeError>;
+    type SerializeStruct = ser::Impossible<String, SerializeError>;
+    type SerializeStructVariant = ser::Impossible<String, SerializeError>;
+
+    fn serialize_i8(self, v: i8) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<String> {
+        self.unsupported()
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<String> {
+        self.unsupported()
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<String> {
+        self.unsupported()
+    }
+
+    fn serialize_char(self, v: char) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<String> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<String> {
+        self.unsupported()
+    }
+
+    fn serialize_none(self) -> Result<String> {
+        self.unsupported()
+    }
+
+    fn serialize_some<T>(self, _value: &T) -> Result<String>
     where
-        T: Serialize,
+        T: Serialize + ?Sized,
     {
-        let mut str = to_string(value)?;
-        str += ", ";
-        self.buf += &str;
-        Ok(())
+        self.unsupported()
     }
 
-    fn end(self) -> Result<Self::Ok> {
-        let mut tuple = (&self.buf[..self.buf.len() - 2 - 1]).to_string();
-        tuple.push(')');
-        let mut root = self.inner.get_mut();
-        root.append(tuple);
-        Ok(())
+    fn serialize_unit(self) -> Result<String> {
+        self.unsupported()
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<String> {
+        self.unsupported()
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> Result<String> {
+        Ok(variant.to_string())
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<String>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<String>
+    where
+        T: Serialize + ?Sized,
+    {
+        self.unsupported()
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq> {
+        self.unsupported()
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple> {
+        self.unsupported()
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct> {
+        self.unsupported()
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant> {
+        self.unsupported()
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap> {
+        self.unsupported()
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct> {
+        self.unsupported()
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant> {
+        self.unsupported()
     }
 }
 
-impl<'a> ser::SerializeTupleVariant for SubSerializer<'a> {
+impl<'a, 'o, W: io::Write> ser::SerializeTupleStruct for SubSerializer<'a, 'o, W> {
     type Ok = ();
 
     type Error = SerializeError;
 
-    fn serialize_field<T: ?Sized>(&mut self, value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: Serialize + ?Sized,
     {
-        <Self as ser::SerializeTupleStruct>::serialize_field(self, value)
+        // Fields are captured instead of emitted so they can be joined into a
+        // single `(a,b,c)` value.
+        let prev = self.inner.tuple.replace(String::new());
+        let result = value.serialize(&mut *self.inner);
+        let field = std::mem::replace(&mut self.inner.tuple, prev).unwrap_or_default();
+        result?;
+        check_item(&field)?;
+        if self.count > 0 {
+            self.buf.push(',');
+        }
+        self.buf += &field;
+        self.count += 1;
+        Ok(())
     }
 
     fn end(self) -> Result<Self::Ok> {
-        <Self as ser::SerializeTupleStruct>::end(self)
+        let tuple = format!("({})", self.buf);
+        self.inner.emit(&tuple)
     }
 }
 
-impl<'a> ser::SerializeStructVariant for SubSerializer<'a> {
+/// The variant's `type` key makes its key a section, so the tuple cannot sit
+/// on the key itself and goes under `data`, like the keys of a curve. The
+/// deserializer reads tuple variants from the same two keys.
+impl<'a, 'o, W: io::Write> ser::SerializeTupleVariant for SubSerializer<'a, 'o, W> {
     type Ok = ();
 
     type Error = SerializeError;
 
-    fn serialize_field<T: ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()>
+    fn serialize_field<T>(&mut self, value: &T) -> Result<()>
     where
-        T: Serialize,
+        T: Serialize + ?Sized,
     {
-        <SubSerializer as ser::SerializeStruct>::serialize_field(self, key, value)
+        <Self as ser::SerializeTupleStruct>::serialize_field(self, value)
     }
 
     fn end(self) -> Result<Self::Ok> {
-        <SubSerializer as ser::SerializeStruct>::end(self)
+        let len = self.inner.push_key("data");
+        let tuple = format!("({})", self.buf);
+        self.inner.emit(&tuple)?;
+        self.inner.pop_key(len);
+        Ok(())
     }
 }
 
-fn debug_tree<T: std::fmt::Debug>(tree: &Tree<T>) {
-    let mut out = String::new();
-    tree.write_formatted(&mut out);
-    println!("{}", out);
-}
+impl<'a, 'o, W: io::Write> ser::SerializeStructVariant for SubSerializer<'a, 'o, W> {
+    type Ok = ();
 
-fn write_to_writer_inner<W: std::io::Write, T: ToString>(
-    mut writer: W,
-    tree: &Tree<T>,
-) -> Result<()> {
-    let root = if let Some(root) = tree.root() {
-        root
-    } else {
-        return Ok(());
-    };
-    for node in root
-        .traverse_post_order()
-        .filter(|x| x.children().count() == 0)
+    type Error = SerializeError;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<()>
+    where
+        T: Serialize + ?Sized,
     {
-        let ancestors: Vec<_> = node.ancestors().collect();
-        let mut line = ancestors
-            .into_iter()
-            .rev()
-            .skip(1)
-            .fold(String::new(), |a, b| a + &b.data().to_string() + ".");
-        line.remove(line.len() - 1);
-        line.push('=');
-        line += &node.data().to_string();
-        line.push('\n');
-        writer.write(line.as_bytes())?;
+        <Self as ser::SerializeStruct>::serialize_field(self, key, value)
     }
-    Ok(())
-}
 
-fn write_tree<T: ToString>(tree: &Tree<T>) -> String {
-    let mut string = String::new();
-    let root = if let Some(root) = tree.root() {
-        root
-    } else {
-        return string;
-    };
-    for node in root
-        .traverse_post_order()
-        .filter(|x| x.children().count() == 0)
-    {
-        let ancestors: Vec<_> = node.ancestors().collect();
-        let mut line = ancestors
-            .into_iter()
-            .rev()
-            .skip(1)
-            .fold(String::new(), |a, b| a + &b.data().to_string() + ".");
-        line.remove(line.len() - 1);
-        line.push('=');
-        line += &node.data().to_string();
-        line.push('\n');
-        string += &line;
+    fn end(self) -> Result<Self::Ok> {
+        <Self as ser::SerializeStruct>::end(self)
     }
-    string
 }
 
 #[test]
 fn serialize_seq() {
     let seq = [39.39, 420.69];
-    let mut serializer = Serializer::new();
-    let ser = seq.serialize(&mut serializer);
 
-    debug_tree(&serializer.tree);
-    println!("{}", write_tree(&serializer.tree));
-    panic!()
+    let result = to_string(&seq).unwrap();
+    let output = "0=39.39
+1=420.69
+length=2
+";
+    assert_eq!(result, output);
 }
 
 #[test]
@@ -485,23 +924,22 @@ fn serialize_struct() {
         baz_array: [u8; 4],
     }
 
-    // #[derive(Serialize)]
-    // struct BazArray {
-    //     baz: Vec<u8>
-    // }
-    // let seq = Test { foo: 69, bar: 39.39, baz_array: BazArray{baz:vec![39, 39, 69, 0]} };
     let seq = Test {
         foo: 69,
         bar: 39.39,
         baz_array: [39, 39, 69, 0],
     };
-    // let seq = Test { foo: 69, bar: 39.39 };
-    let mut serializer = Serializer::new();
-    let ser = seq.serialize(&mut serializer);
 
-    debug_tree(&serializer.tree);
-    println!("{}", write_tree(&serializer.tree));
-    panic!()
+    let result = to_string(&seq).unwrap();
+    let output = "bar=39.39
+baz_array.0=39
+baz_array.1=39
+baz_array.2=69
+baz_array.3=0
+baz_array.length=4
+foo=69
+";
+    assert_eq!(result, output);
 }
 
 #[test]
@@ -546,3 +984,346 @@ _.property.version=20050706
 ";
     assert_eq!(result, output);
 }
+
+#[test]
+fn serialize_sorts_keys() {
+    use serde_derive::*;
+    #[derive(Serialize)]
+    struct Key(u32, f32);
+
+    #[derive(Serialize)]
+    struct Curve {
+        r#type: u8,
+        key: Vec<Key>,
+        max: u32,
+    }
+
+    let curve = Curve {
+        r#type: 3,
+        key: (0..11).map(|i| Key(i * 10, 0.5)).collect(),
+        max: 100,
+    };
+
+    let result = to_string(&curve).unwrap();
+    let keys: Vec<_> = result
+        .lines()
+        .map(|x| x.split('=').next().unwrap())
+        .collect();
+    assert_eq!(&keys[..4], &["key.0", "key.1", "key.10", "key.2"]);
+    assert_eq!(&keys[11..], &["key.length", "max", "type"]);
+    assert!(result.contains("key.10=(100,0.5)\n"));
+}
+
+#[cfg(test)]
+struct ShortWriter {
+    out: Vec<u8>,
+    chunk: usize,
+    capacity: usize,
+}
+
+#[cfg(test)]
+impl io::Write for ShortWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf
+            .len()
+            .min(self.chunk)
+            .min(self.capacity - self.out.len());
+        if len == 0 && !buf.is_empty() {
+            return Err(io::ErrorKind::StorageFull.into());
+        }
+        self.out.extend_from_slice(&buf[..len]);
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+struct BrokenPipe;
+
+#[cfg(test)]
+impl io::Write for BrokenPipe {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Err(io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+#[test]
+fn to_writer_handles_short_writes() {
+    let seq = [39.39, 420.69];
+    let mut writer = ShortWriter {
+        out: Vec::new(),
+        chunk: 3,
+        capacity: usize::MAX,
+    };
+
+    to_writer(&mut writer, &seq).unwrap();
+    assert_eq!(writer.out, to_string(&seq).unwrap().as_bytes());
+}
+
+#[test]
+fn to_writer_reports_full_disk() {
+    let seq = [39.39, 420.69];
+    let mut writer = ShortWriter {
+        out: Vec::new(),
+        chunk: 3,
+        capacity: 10,
+    };
+
+    let err = to_writer(&mut writer, &seq).unwrap_err();
+    assert!(matches!(err, SerializeError::Io(ref e) if e.kind() == io::ErrorKind::StorageFull));
+}
+
+#[test]
+fn to_writer_reports_broken_pipe() {
+    let err = to_writer(BrokenPipe, &[39.39, 420.69]).unwrap_err();
+    assert!(matches!(err, SerializeError::Io(ref e) if e.kind() == io::ErrorKind::BrokenPipe));
+}
+
+#[test]
+fn serialize_with_options() {
+    use chrono::NaiveDate;
+    use serde_derive::*;
+    #[derive(Serialize)]
+    struct PlayControl {
+        size: u32,
+        fps: f32,
+        begin: u32,
+    }
+
+    let play_control = PlayControl {
+        size: 9301,
+        fps: 60.0,
+        begin: 0,
+    };
+    let date = NaiveDate::from_ymd_opt(2009, 12, 30)
+        .unwrap()
+        .and_hms_opt(15, 32, 2)
+        .unwrap();
+    let options = SerializerOptions::new()
+        .header(true)
+        .timestamp(Timestamp::Fixed(date))
+        .line_ending(LineEnding::CrLf)
+        .key_order(KeyOrder::Declaration);
+
+    let result = to_string_with(&play_control, &options).unwrap();
+    let output =
+        "#A3DA__________\r\n#Wed Dec 30 15:32:02 2009\r\nsize=9301\r\nfps=60\r\nbegin=0\r\n";
+    assert_eq!(result, output);
+}
+
+#[test]
+fn serialize_float_precision() {
+    let options = SerializerOptions::new().float_precision(Some(6));
+    let values = [1.777777, 3.314019e-6, 0.93616, 9301.0, -0.0140866, 1.5e7];
+
+    let result = to_string_with(&values, &options).unwrap();
+    let values: Vec<_> = result
+        .lines()
+        .map(|x| x.split('=').nth(1).unwrap())
+        .collect();
+    assert_eq!(
+        values,
+        [
+            "1.77778",
+            "3.31402e-006",
+            "0.93616",
+            "9301",
+            "-0.0140866",
+            "1.5e+007",
+            "6"
+        ]
+    );
+}
+
+#[test]
+fn serialize_rejects_unparsable_values() {
+    use serde_derive::*;
+    #[derive(Serialize)]
+    struct Event<'a> {
+        param: &'a str,
+    }
+
+    let result = to_string(&Event { param: "a=b c" }).unwrap();
+    assert_eq!(result, "param=a=b c\n");
+    for param in &["two\nlines", " padded", "crlf\r"] {
+        let err = to_string(&Event { param }).unwrap_err();
+        assert!(matches!(err, SerializeError::InvalidValue(_)));
+    }
+
+    #[derive(Serialize)]
+    struct Pair<'a>(&'a str, u32);
+
+    assert_eq!(
+        to_string(&[Pair("a b", 1)]).unwrap(),
+        "0=(a b,1)\nlength=1\n"
+    );
+    for item in &["a,b", "(a", "a)"] {
+        let err = to_string(&[Pair(item, 1)]).unwrap_err();
+        assert!(matches!(err, SerializeError::InvalidValue(ref x) if x == item));
+    }
+
+    #[derive(Serialize)]
+    struct Nested<'a>(Pair<'a>, u32);
+
+    let err = to_string(&[Nested(Pair("a", 1), 2)]).unwrap_err();
+    assert!(matches!(err, SerializeError::Message(_)));
+
+    #[derive(Serialize)]
+    struct Inner {
+        a: u32,
+        b: u32,
+    }
+    #[derive(Serialize)]
+    struct Outer(u32, Inner);
+    #[derive(Serialize)]
+    struct Listed(u32, Vec<u32>);
+    #[derive(Serialize)]
+    enum Kind {
+        Value(u32),
+    }
+    #[derive(Serialize)]
+    struct Tagged(u32, Kind);
+
+    let err = to_string(&[Outer(1, Inner { a: 2, b: 3 })]).unwrap_err();
+    assert!(matches!(err, SerializeError::Message(_)));
+    let err = to_string(&[Listed(1, vec![2, 3])]).unwrap_err();
+    assert!(matches!(err, SerializeError::Message(_)));
+    let err = to_string(&[Tagged(1, Kind::Value(2))]).unwrap_err();
+    assert!(matches!(err, SerializeError::Message(_)));
+}
+
+#[test]
+fn serialize_maps() {
+    use serde_derive::*;
+    use std::collections::BTreeMap;
+    #[derive(Serialize)]
+    struct Object {
+        frame: u32,
+    }
+
+    #[derive(Serialize)]
+    struct Test {
+        object: BTreeMap<String, Object>,
+        motion: BTreeMap<u32, String>,
+    }
+
+    let mut test = Test {
+        object: BTreeMap::new(),
+        motion: BTreeMap::new(),
+    };
+    test.object
+        .insert("STGPV001_BG".to_string(), Object { frame: 3 });
+    test.motion.insert(10, "walk".to_string());
+    test.motion.insert(2, "idle".to_string());
+
+    let result = to_string(&test).unwrap();
+    let output = "motion.10=walk
+motion.2=idle
+object.STGPV001_BG.frame=3
+";
+    assert_eq!(result, output);
+
+    for key in &["a.b", "a=b", ""] {
+        let mut map = BTreeMap::new();
+        map.insert(*key, 1);
+        let err = to_string(&map).unwrap_err();
+        assert!(matches!(err, SerializeError::InvalidMapKey(ref x) if x == key));
+    }
+    let mut map = BTreeMap::new();
+    map.insert((1, 2), 1);
+    assert!(matches!(to_string(&map), Err(SerializeError::Message(_))));
+}
+
+#[test]
+fn serialize_shift_jis() {
+    use serde_derive::*;
+    #[derive(Serialize)]
+    struct Object {
+        name: &'static str,
+    }
+
+    let object = Object {
+        name: "ステージ"
+    };
+    let options = SerializerOptions::new().encoding(TextEncoding::ShiftJis);
+    let mut bytes = Vec::new();
+    to_writer_with(&mut bytes, &object, &options).unwrap();
+    assert_eq!(bytes, b"name=\x83X\x83e\x81[\x83W\n");
+    assert_eq!(
+        to_string_with(&object, &options).unwrap(),
+        "name=ステージ\n"
+    );
+
+    let object = Object { name: "☃︎" };
+    let err = to_writer_with(&mut Vec::new(), &object, &options).unwrap_err();
+    assert!(matches!(
+        err,
+        SerializeError::Unencodable(_, TextEncoding::ShiftJis)
+    ));
+}
+
+#[test]
+fn serialize_units() {
+    use serde_derive::*;
+    #[derive(Serialize)]
+    struct Marker;
+
+    #[derive(Serialize)]
+    struct Test {
+        a: u32,
+        flag: Option<()>,
+        marker: Marker,
+    }
+
+    assert_eq!(to_string(&()).unwrap(), "");
+    assert_eq!(to_value(&Marker).unwrap(), Value::default());
+    let test = Test {
+        a: 1,
+        flag: Some(()),
+        marker: Marker,
+    };
+    assert_eq!(to_string(&test).unwrap(), "a=1\n");
+
+    struct Bytes;
+
+    impl Serialize for Bytes {
+        fn serialize<S: ser::Serializer>(
+            &self,
+            serializer: S,
+        ) -> std::result::Result<S::Ok, S::Error> {
+            serializer.serialize_bytes(b"a3da")
+        }
+    }
+    let mut map = std::collections::BTreeMap::new();
+    map.insert("bytes", Bytes);
+    assert!(matches!(to_string(&map), Err(SerializeError::Message(_))));
+}
+
+#[test]
+fn serialize_tuple_variant() {
+    use serde_derive::*;
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Point(f32),
+        Pair(u32, f32),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Test {
+        shape: Shape,
+    }
+
+    let test = Test {
+        shape: Shape::Pair(1, 2.5),
+    };
+    let output = to_string(&test).unwrap();
+    assert_eq!(output, "shape.data=(1,2.5)\nshape.type=1\n");
+    assert_eq!(crate::from_str::<Test>(&output).unwrap(), test);
+}
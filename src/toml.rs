@@ -0,0 +1,57 @@
+//! TOML export for reviewing A3DA data by eye.
+//!
+//! TOML documents must be tables, so only sections can be exported; a bare
+//! scalar is an error. Otherwise this mirrors the YAML export.
+
+use ::toml::Value as Toml;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::error::{DeserializeError, SerializeError};
+
+pub fn to_toml<T>(value: &T) -> Result<String, SerializeError>
+where
+    T: Serialize + ?Sized,
+{
+    let mut value =
+        Toml::try_from(value).map_err(|err| SerializeError::Message(err.to_string()))?;
+    shorten_floats(&mut value);
+    ::toml::to_string(&value).map_err(|err| SerializeError::Message(err.to_string()))
+}
+
+pub fn from_toml<T>(input: &str) -> Result<T, DeserializeError>
+where
+    T: DeserializeOwned,
+{
+    ::toml::from_str(input).map_err(|err| DeserializeError::Message(err.to_string()))
+}
+
+/// TOML only has 64-bit floats, so an `f32` such as `0.93616` would be written
+/// as `0.9361600279808044`. Floats that are exactly an `f32` are written with
+/// the shortest digits that read back as that `f32` instead.
+fn shorten_floats(value: &mut Toml) {
+    match value {
+        Toml::Float(x) if f64::from(*x as f32) == *x => {
+            *x = (*x as f32).to_string().parse().unwrap_or(*x);
+        }
+        Toml::Array(x) => x.iter_mut().for_each(shorten_floats),
+        Toml::Table(x) => x.iter_mut().for_each(|(_, x)| shorten_floats(x)),
+        _ => {}
+    }
+}
+
+#[test]
+fn toml_round_trip() {
+    use crate::model::A3da;
+    use crate::{from_str, Value};
+
+    let input = std::fs::read_to_string("assets/CAMPV001_BASE.a3da").unwrap();
+    let a3da: A3da = from_str(&input).unwrap();
+    let toml = to_toml(&a3da).unwrap();
+    assert!(toml.contains("[camera_root.view_point.fov.Static]\nvalue = 0.93616\n"));
+    assert_eq!(from_toml::<A3da>(&toml).unwrap(), a3da);
+
+    let value = Value::parse(&input).unwrap();
+    let toml = to_toml(&value).unwrap();
+    assert_eq!(from_toml::<Value>(&toml).unwrap(), value);
+    assert!(to_toml(&Value::from("1")).is_err());
+}
@@ -0,0 +1,348 @@
+use serde::de::{self, Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use serde::ser::{Serialize, SerializeMap, Serializer};
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::de::{parse_value, parse_value_lenient, DeserializerOptions};
+use crate::error::{DeserializeError, Diagnostic};
+use crate::key::{KeyGlob, KeyPath};
+use crate::ser::format_float;
+
+/// A key tree without a Rust type, as produced by `to_value`.
+///
+/// Every `key=value` line is a path of sections ending in a scalar, so
+/// `a.b=1` is a section `a` holding a section whose `b` is `Scalar("1")`.
+/// Values keep their textual form; sequences are sections with `0`, `1`, ...
+/// and `length` children, as in the file. Children are ordered by key, which
+/// makes serializing a `Value` write the same lines `to_string` would.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Scalar(String),
+    Section(BTreeMap<String, Value>),
+}
+
+impl Value {
+    /// Indexes A3DA text by key, keeping every value as written. Like
+    /// `from_str`, fails on a key set twice or set as both a value and a
+    /// section.
+    pub fn parse(input: &str) -> Result<Self, DeserializeError> {
+        Self::parse_with(input, &DeserializerOptions::default())
+    }
+
+    /// Like `parse`, but resolves keys set twice as `options` says.
+    pub fn parse_with(
+        input: &str,
+        options: &DeserializerOptions,
+    ) -> Result<Self, DeserializeError> {
+        parse_value(input, options)
+    }
+
+    /// Like `parse`, but carries on past malformed lines and keys set twice,
+    /// keeping the first line, as `from_str_lenient` does. Returns every
+    /// problem found, in line order.
+    pub fn parse_lenient(input: &str) -> (Self, Vec<Diagnostic>) {
+        parse_value_lenient(input)
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::Scalar(x) => Some(x),
+            Value::Section(_) => None,
+        }
+    }
+
+    pub fn as_section(&self) -> Option<&BTreeMap<String, Value>> {
+        match self {
+            Value::Scalar(_) => None,
+            Value::Section(x) => Some(x),
+        }
+    }
+
+    /// Looks up a dotted key below this value.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        key.split('.').try_fold(self, |value, name| match value {
+            Value::Section(x) => x.get(name),
+            Value::Scalar(_) => None,
+        })
+    }
+
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        key.split('.').try_fold(self, |value, name| match value {
+            Value::Section(x) => x.get_mut(name),
+            Value::Scalar(_) => None,
+        })
+    }
+
+    /// Sets a dotted key, creating the sections leading to it. Scalars in the
+    /// way are replaced by sections. Returns the value previously at `key`.
+    pub fn insert(&mut self, key: &str, value: impl Into<Value>) -> Option<Value> {
+        let mut parent = self;
+        let mut names = key.split('.').peekable();
+        while let Some(name) = names.next() {
+            if let Value::Scalar(_) = parent {
+                *parent = Value::default();
+            }
+            let section = match parent {
+                Value::Section(x) => x,
+                Value::Scalar(_) => unreachable!(),
+            };
+            if names.peek().is_none() {
+                return section.insert(name.to_string(), value.into());
+            }
+            parent = section.entry(name.to_string()).or_default();
+        }
+        None
+    }
+
+    /// Removes a dotted key and everything below it.
+    pub fn remove(&mut self, key: &str) -> Option<Value> {
+        let (parent, name) = match key.rsplit_once('.') {
+            Some((parent, name)) => (self.get_mut(parent)?, name),
+            None => (self, key),
+        };
+        match parent {
+            Value::Section(x) => x.remove(name),
+            Value::Scalar(_) => None,
+        }
+    }
+
+    /// The dotted key and value of every scalar below this value, in key
+    /// order.
+    pub fn leaves(&self) -> Vec<(String, &str)> {
+        let mut leaves = Vec::new();
+        self.collect_leaves(&mut String::new(), &mut leaves);
+        leaves
+    }
+
+    /// Every scalar whose key matches `glob`, in key order.
+    pub fn query(&self, glob: &KeyGlob) -> Vec<(KeyPath, &str)> {
+        self.leaves()
+            .into_iter()
+            .filter_map(|(key, value)| Some((KeyPath::parse(&key).ok()?, value)))
+            .filter(|(key, _)| glob.matches(key))
+            .collect()
+    }
+
+    /// Rewrites every number with a fraction or exponent, including the items
+    /// of `(…)` tuples, with `precision` significant digits like
+    /// `SerializerOptions::float_precision`. Integers are left alone.
+    pub fn format_floats(&mut self, precision: usize) {
+        match self {
+            Value::Scalar(x) => {
+                let formatted = match x.strip_prefix('(').and_then(|x| x.strip_suffix(')')) {
+                    Some(items) => {
+                        let items: Vec<_> = items
+                            .split(',')
+                            .map(|x| format_number(x.trim(), precision))
+                            .collect();
+                        format!("({})", items.join(","))
+                    }
+                    None => format_number(x, precision),
+                };
+                *x = formatted;
+            }
+            Value::Section(x) => {
+                for value in x.values_mut() {
+                    value.format_floats(precision);
+                }
+            }
+        }
+    }
+
+    fn collect_leaves<'a>(&'a self, key: &mut String, leaves: &mut Vec<(String, &'a str)>) {
+        match self {
+            Value::Scalar(x) => leaves.push((key.clone(), x)),
+            Value::Section(x) => {
+                for (name, value) in x {
+                    let len = key.len();
+                    if len > 0 {
+                        key.push('.');
+                    }
+                    key.push_str(name);
+                    value.collect_leaves(key, leaves);
+                    key.truncate(len);
+                }
+            }
+        }
+    }
+}
+
+fn format_number(x: &str, precision: usize) -> String {
+    match x.parse::<f64>() {
+        Ok(v) if v.is_finite() && x.contains(['.', 'e', 'E']) => format_float(v, precision.max(1)),
+        _ => x.to_string(),
+    }
+}
+
+impl Default for Value {
+    fn default() -> Self {
+        Value::Section(BTreeMap::new())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Scalar(value)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Scalar(value.to_string())
+    }
+}
+
+/// Sections serialize as maps and scalars as strings, so `to_string` writes a
+/// `Value` back out as the lines it was built from.
+impl Serialize for Value {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Value::Scalar(x) => serializer.serialize_str(x),
+            Value::Section(x) => {
+                let mut map = serializer.serialize_map(Some(x.len()))?;
+                for (name, value) in x {
+                    map.serialize_entry(name, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+/// The inverse of `Serialize`, for reading a tree back from self-describing
+/// formats. Numbers and booleans become scalars written the way `to_string`
+/// writes them, and sequences become sections with a `length`.
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ValueVisitor;
+
+        impl<'de> Visitor<'de> for ValueVisitor {
+            type Value = Value;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a scalar or a section of keys")
+            }
+
+            fn visit_bool<E: de::Error>(self, v: bool) -> Result<Value, E> {
+                Ok(Value::Scalar(u8::from(v).to_string()))
+            }
+
+            fn visit_i64<E: de::Error>(self, v: i64) -> Result<Value, E> {
+                Ok(Value::Scalar(v.to_string()))
+            }
+
+            fn visit_u64<E: de::Error>(self, v: u64) -> Result<Value, E> {
+                Ok(Value::Scalar(v.to_string()))
+            }
+
+            fn visit_f64<E: de::Error>(self, v: f64) -> Result<Value, E> {
+                Ok(Value::Scalar(v.to_string()))
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<Value, E> {
+                Ok(Value::from(v))
+            }
+
+            fn visit_string<E: de::Error>(self, v: String) -> Result<Value, E> {
+                Ok(Value::from(v))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+                let mut section = BTreeMap::new();
+                while let Some(value) = seq.next_element()? {
+                    section.insert(section.len().to_string(), value);
+                }
+                let length = Value::Scalar(section.len().to_string());
+                section.insert("length".to_string(), length);
+                Ok(Value::Section(section))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+                let mut section = BTreeMap::new();
+                while let Some((key, value)) = map.next_entry()? {
+                    section.insert(key, value);
+                }
+                Ok(Value::Section(section))
+            }
+        }
+
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+#[test]
+fn value_paths() {
+    let mut value = Value::default();
+    assert_eq!(value.insert("a.b.c", "1"), None);
+    assert_eq!(value.insert("a.d", "2"), None);
+    assert_eq!(value.insert("a.b.c", "3"), Some(Value::from("1")));
+    assert_eq!(value.get("a.b.c").and_then(Value::as_str), Some("3"));
+    assert_eq!(value.get("a.b.c.x"), None);
+    let glob = KeyGlob::parse("**.c").unwrap();
+    assert_eq!(
+        value.query(&glob),
+        [(KeyPath::parse("a.b.c").unwrap(), "3")]
+    );
+    assert_eq!(
+        value.leaves(),
+        [("a.b.c".to_string(), "3"), ("a.d".to_string(), "2")]
+    );
+
+    value.insert("a.d.e", "4");
+    assert_eq!(value.get("a.d.e").and_then(Value::as_str), Some("4"));
+    let removed = value.remove("a.b").unwrap();
+    assert_eq!(removed.get("c").and_then(Value::as_str), Some("3"));
+    assert_eq!(value.remove("a.b"), None);
+    assert_eq!(crate::to_string(&value).unwrap(), "a.d.e=4\n");
+}
+
+#[test]
+fn value_parse_and_format() {
+    let input = "#A3DA__________
+a . b=60.000
+a.c=(0,-0.4698220,3.314020e-06)
+a.d=1
+a.e=name.ext
+";
+    let mut value = Value::parse(input).unwrap();
+    value.format_floats(6);
+    assert_eq!(
+        crate::to_string(&value).unwrap(),
+        "a.b=60\na.c=(0,-0.469822,3.31402e-006)\na.d=1\na.e=name.ext\n"
+    );
+    assert!(Value::parse("a\n").is_err());
+}
+
+#[test]
+fn value_parse_duplicates() {
+    use crate::de::DuplicateKeys;
+
+    let input = "a.b=1\na.b=2\na.c=3\na.c.d=4\n";
+    assert!(matches!(
+        Value::parse(input),
+        Err(DeserializeError::DuplicateKey {
+            first: 1,
+            second: 2,
+            ..
+        })
+    ));
+
+    let options = DeserializerOptions::new().duplicate_keys(DuplicateKeys::LastWins);
+    let value = Value::parse_with(input, &options).unwrap();
+    assert_eq!(crate::to_string(&value).unwrap(), "a.b=2\na.c.d=4\n");
+
+    let (value, diagnostics) = Value::parse_lenient(input);
+    assert_eq!(crate::to_string(&value).unwrap(), "a.b=1\na.c=3\n");
+    let lines: Vec<_> = diagnostics
+        .iter()
+        .map(|x| (x.key.as_str(), x.line))
+        .collect();
+    assert_eq!(lines, [("a.b", 2), ("a.c", 4)]);
+}
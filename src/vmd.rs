@@ -0,0 +1,600 @@
+//! Conversion between MikuMikuDance `.vmd` camera motion and A3DA cameras.
+//!
+//! A VMD camera orbits the point it looks at: each keyframe holds that point,
+//! a distance from it, Euler angles and a vertical FOV in degrees, at 30
+//! frames per second. MMD is left-handed, so Z and the X and Y angles are
+//! negated. Positions are scaled from MMD units to metres.
+//!
+//! The point looked at, the roll and the FOV keep their keyframes as Hermite
+//! curves, with tangents taken from the ends of each segment's Bezier timing
+//! curve. The eye depends on the distance and angles together, so it is
+//! baked per frame.
+//!
+//! Going the other way, the camera is sampled at 30 frames per second and
+//! split back into a point, distance and angles. Keyframes that linear
+//! interpolation between their neighbours reproduces are dropped.
+
+use std::convert::TryInto;
+use std::f32::consts::PI;
+
+use crate::encoding::{encode, TextEncoding};
+use crate::error::{DeserializeError, SerializeError};
+use crate::math::{camera_pose, look_at, yfov};
+use crate::model::{
+    A3da, CameraRoot, Curve, Key, Metadata, PlayControl, Transform, Vec3, Version, ViewPoint,
+};
+
+/// The frame rate of VMD motions.
+const VMD_FPS: f32 = 30.0;
+
+/// The model name MMD gives camera and light motions.
+const CAMERA_MODEL: &str = "\u{30ab}\u{30e1}\u{30e9}\u{30fb}\u{7167}\u{660e}";
+
+/// Bezier control points for a straight line.
+const LINEAR: [u8; 4] = [20, 107, 20, 107];
+
+/// Controls how `from_vmd` and `to_vmd` convert a motion.
+#[derive(Debug, Clone)]
+pub struct VmdOptions {
+    scale: f32,
+    fps: f32,
+    aspect: f32,
+    tolerance: f32,
+}
+
+impl VmdOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Metres per MMD unit, 0.08 by default.
+    pub fn scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    /// The frame rate of the A3DA animation made by `from_vmd`, 60 by
+    /// default. `to_vmd` uses the file's own `play_control.fps`.
+    pub fn fps(mut self, fps: f32) -> Self {
+        self.fps = fps;
+        self
+    }
+
+    /// The aspect ratio written to the view point by `from_vmd`, 16:9 by
+    /// default.
+    pub fn aspect(mut self, aspect: f32) -> Self {
+        self.aspect = aspect;
+        self
+    }
+
+    /// How far `to_vmd` lets the motion stray when dropping keyframes, in MMD
+    /// units for positions and radians for angles. 0.001 by default; 0 keeps
+    /// every frame that changes.
+    pub fn tolerance(mut self, tolerance: f32) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+}
+
+impl Default for VmdOptions {
+    fn default() -> Self {
+        Self {
+            scale: 0.08,
+            fps: 60.0,
+            aspect: 16.0 / 9.0,
+            tolerance: 0.001,
+        }
+    }
+}
+
+/// One camera keyframe of a VMD file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct CameraFrame {
+    frame: u32,
+    distance: f32,
+    position: [f32; 3],
+    rotation: [f32; 3],
+    /// Bezier control points `x1, x2, y1, y2` from 0 to 127 for X, Y, Z,
+    /// rotation, distance and FOV, shaping the segment ending at this frame.
+    interpolation: [[u8; 4]; 6],
+    /// Vertical FOV in degrees.
+    fov: u32,
+}
+
+/// Reads the camera motion of a VMD file into an A3DA with one camera.
+///
+/// The metadata has the usual converter versions and an empty `file_name`.
+pub fn from_vmd(bytes: &[u8], options: &VmdOptions) -> Result<A3da, DeserializeError> {
+    let mut frames = read_cameras(bytes)?;
+    frames.sort_by_key(|x| x.frame);
+    // Later keyframes at the same frame win, as in MMD.
+    frames.reverse();
+    frames.dedup_by_key(|x| x.frame);
+    frames.reverse();
+    if frames.is_empty() {
+        return Err(DeserializeError::Message(
+            "VMD file has no camera keyframes".to_string(),
+        ));
+    }
+
+    let ratio = options.fps / VMD_FPS;
+    let times: Vec<_> = frames.iter().map(|x| x.frame as f32 * ratio).collect();
+    let size = times[times.len() - 1] + 1.0;
+    let channel = |i: usize, value: &dyn Fn(&CameraFrame) -> f32| {
+        let values: Vec<_> = frames.iter().map(value).collect();
+        let interpolation: Vec<_> = frames.iter().map(|x| x.interpolation[i]).collect();
+        let cuts: Vec<_> = frames
+            .windows(2)
+            .map(|x| x[1].frame - x[0].frame == 1)
+            .collect();
+        hermite(&times, &values, &interpolation, &cuts, size)
+    };
+    let scale = options.scale;
+    let target = Vec3 {
+        x: channel(0, &|x| x.position[0] * scale),
+        y: channel(1, &|x| x.position[1] * scale),
+        z: channel(2, &|x| -x.position[2] * scale),
+    };
+    let pitch = channel(3, &|x| -x.rotation[0]);
+    let yaw = channel(3, &|x| -x.rotation[1]);
+    let roll = channel(3, &|x| x.rotation[2]);
+    let distance = channel(4, &|x| x.distance * scale);
+    let fov = channel(5, &|x| (x.fov as f32).to_radians());
+
+    // The eye sits `distance` behind the target along the camera's -Z,
+    // turned by yaw about Y after pitch about X; MMD distances are negative.
+    let count = size as usize;
+    let mut eye = [Vec::new(), Vec::new(), Vec::new()];
+    for i in 0..count {
+        let frame = i as f32;
+        let at = |x: &Curve| x.value_at(frame).unwrap_or(0.0);
+        let (pitch, yaw, distance) = (at(&pitch), at(&yaw), -at(&distance));
+        let offset = [
+            distance * pitch.cos() * yaw.sin(),
+            -distance * pitch.sin(),
+            distance * pitch.cos() * yaw.cos(),
+        ];
+        let target = [&target.x, &target.y, &target.z].map(at);
+        for axis in 0..3 {
+            eye[axis].push(Key::Linear(frame, target[axis] + offset[axis]));
+        }
+    }
+    let [x, y, z] = eye.map(|key| baked(key, size));
+
+    let camera = CameraRoot {
+        interest: Transform {
+            rot: vec3(|| Curve::None {}),
+            scale: vec3(|| Curve::Static { value: 1.0 }),
+            trans: target,
+            visibility: Curve::Static { value: 1.0 },
+        },
+        rot: vec3(|| Curve::None {}),
+        scale: vec3(|| Curve::Static { value: 1.0 }),
+        trans: vec3(|| Curve::None {}),
+        view_point: ViewPoint {
+            aspect: options.aspect,
+            camera_aperture_h: None,
+            camera_aperture_w: None,
+            focal_length: None,
+            fov: Some(fov),
+            fov_is_horizontal: false,
+            roll,
+            rot: vec3(|| Curve::None {}),
+            scale: vec3(|| Curve::Static { value: 1.0 }),
+            trans: Vec3 { x, y, z },
+            visibility: Curve::Static { value: 1.0 },
+        },
+        visibility: Curve::Static { value: 1.0 },
+    };
+    Ok(A3da {
+        metadata: Metadata {
+            converter: Version { version: 20050823 },
+            file_name: String::new(),
+            property: Version { version: 20050706 },
+        },
+        play_control: PlayControl {
+            begin: 0.0,
+            div: None,
+            fps: options.fps,
+            offset: None,
+            size,
+        },
+        camera_root: vec![camera],
+        object: Vec::new(),
+        object_list: Vec::new(),
+        objhrc: Vec::new(),
+        objhrc_list: Vec::new(),
+    })
+}
+
+/// Writes the first camera of `a3da` as a VMD camera motion.
+///
+/// The FOV is rounded to whole degrees, as VMD stores it. Cuts between two
+/// frames of the camera land between two VMD keyframes one frame apart, which
+/// MMD plays as a jump.
+pub fn to_vmd(a3da: &A3da, options: &VmdOptions) -> Result<Vec<u8>, SerializeError> {
+    let camera = a3da
+        .camera_root
+        .first()
+        .ok_or_else(|| SerializeError::Message("A3DA file has no camera".to_string()))?;
+    let play_control = &a3da.play_control;
+    let ratio = play_control.fps / VMD_FPS;
+    let count = (play_control.size / ratio).ceil().max(1.0) as u32;
+
+    let mut samples: Vec<[f32; 8]> = Vec::with_capacity(count as usize);
+    for frame in 0..count {
+        let pose = camera_pose(camera, play_control.begin + frame as f32 * ratio);
+        let forward = [0, 1, 2].map(|i| pose.interest[i] - pose.eye[i]);
+        let distance = forward.iter().map(|x| x * x).sum::<f32>().sqrt();
+        let (pitch, yaw) = match distance > 1e-6 {
+            true => (
+                (forward[1] / distance).clamp(-1.0, 1.0).asin(),
+                (-forward[0]).atan2(-forward[2]),
+            ),
+            false => (0.0, 0.0),
+        };
+        // The roll is about the line of sight from the world's up, which
+        // differs from the pose's when the root is rotated.
+        let level = look_at(pose.eye, pose.interest, [0.0, 1.0, 0.0], 0.0);
+        let rotation = pose.rotation();
+        let twist = [
+            level[3] * rotation[2] - level[2] * rotation[3] - level[0] * rotation[1]
+                + level[1] * rotation[0],
+            [0, 1, 2, 3].iter().map(|&i| level[i] * rotation[i]).sum(),
+        ];
+        let roll = 2.0 * twist[0].atan2(twist[1]);
+
+        let fov = yfov(
+            &camera.view_point,
+            frame as f32 * ratio + play_control.begin,
+        );
+        let mut sample = [
+            pose.interest[0] / options.scale,
+            pose.interest[1] / options.scale,
+            -pose.interest[2] / options.scale,
+            -pitch,
+            -yaw,
+            roll,
+            -distance / options.scale,
+            fov.to_degrees().round(),
+        ];
+        // Keep angles within half a turn of the last frame's, so MMD turns
+        // the short way.
+        if let Some(previous) = samples.last() {
+            for i in 3..6 {
+                sample[i] -= ((sample[i] - previous[i]) / (2.0 * PI)).round() * 2.0 * PI;
+            }
+        }
+        samples.push(sample);
+    }
+
+    let frames: Vec<_> = reduce(&samples, options.tolerance)
+        .into_iter()
+        .map(|i| {
+            let x = samples[i];
+            CameraFrame {
+                frame: i as u32,
+                distance: x[6],
+                position: [x[0], x[1], x[2]],
+                rotation: [x[3], x[4], x[5]],
+                interpolation: [LINEAR; 6],
+                fov: x[7] as u32,
+            }
+        })
+        .collect();
+    write_cameras(&frames)
+}
+
+/// The indices of the samples to keep so that linear interpolation between
+/// them stays within `tolerance` of every sample dropped. The first and last
+/// are always kept.
+fn reduce(samples: &[[f32; 8]], tolerance: f32) -> Vec<usize> {
+    let mut kept = vec![0];
+    let mut start = 0;
+    while start + 1 < samples.len() {
+        let fits = |end: usize| {
+            (start + 1..end).all(|i| {
+                let t = (i - start) as f32 / (end - start) as f32;
+                (0..8).all(|c| {
+                    let (a, b) = (samples[start][c], samples[end][c]);
+                    // FOV is whole degrees, so it only needs to round right.
+                    let tolerance = if c == 7 { 0.5 } else { tolerance };
+                    (a + (b - a) * t - samples[i][c]).abs() <= tolerance
+                })
+            })
+        };
+        let mut end = start + 1;
+        while end + 1 < samples.len() && fits(end + 1) {
+            end += 1;
+        }
+        kept.push(end);
+        start = end;
+    }
+    kept
+}
+
+fn vec3(curve: impl Fn() -> Curve) -> Vec3 {
+    Vec3 {
+        x: curve(),
+        y: curve(),
+        z: curve(),
+    }
+}
+
+/// A curve through keys sampled every frame, or a static one if they never
+/// change.
+fn baked(key: Vec<Key>, size: f32) -> Curve {
+    let first = key[0].value();
+    match key.iter().all(|x| x.value() == first) {
+        true => Curve::Static {
+            value: first.unwrap_or(0.0),
+        },
+        false => Curve::Linear { key, max: size },
+    }
+}
+
+/// A Hermite curve through `values` at `frames`, each segment leaving and
+/// arriving at the slopes of its Bezier timing curve. `cuts` marks segments
+/// one VMD frame long, which MMD treats as a jump; they hold the old value
+/// until the frame before the new key.
+fn hermite(
+    frames: &[f32],
+    values: &[f32],
+    interpolation: &[[u8; 4]],
+    cuts: &[bool],
+    size: f32,
+) -> Curve {
+    if values.iter().all(|&x| x == values[0]) {
+        return Curve::Static { value: values[0] };
+    }
+    let n = values.len();
+    let (mut ins, mut outs) = (vec![0.0; n], vec![0.0; n]);
+    for i in 1..n {
+        if cuts[i - 1] {
+            continue;
+        }
+        let slope = (values[i] - values[i - 1]) / (frames[i] - frames[i - 1]);
+        let (start, end) = bezier_slopes(interpolation[i]);
+        outs[i - 1] = start * slope;
+        ins[i] = end * slope;
+    }
+
+    let mut key = Vec::with_capacity(n);
+    for i in 0..n {
+        if i > 0 && cuts[i - 1] && frames[i] - 1.0 > frames[i - 1] {
+            key.push(Key::HermiteTangents(
+                frames[i] - 1.0,
+                values[i - 1],
+                0.0,
+                0.0,
+            ));
+        }
+        key.push(Key::HermiteTangents(frames[i], values[i], ins[i], outs[i]));
+    }
+    Curve::Hermite { key, max: size }
+}
+
+/// The slopes at the start and end of a Bezier timing curve from (0, 0) to
+/// (127, 127) through the control points `x1, x2, y1, y2`, relative to a
+/// straight line. They are clamped to 3, beyond which a Hermite segment
+/// overshoots its ends while the Bezier never does.
+fn bezier_slopes([x1, x2, y1, y2]: [u8; 4]) -> (f32, f32) {
+    let points = [(0, 0), (x1, y1), (x2, y2), (127, 127)].map(|(x, y)| (x as f32, y as f32));
+    let slope = |a: (f32, f32), b: (f32, f32)| {
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        match dx > 0.0 {
+            true => (dy / dx).clamp(0.0, 3.0),
+            false => 3.0,
+        }
+    };
+    // A control point on an end leaves the tangent to the next one along.
+    let start = points[1..].iter().find(|&&x| x != points[0]);
+    let end = points[..3].iter().rev().find(|&&x| x != points[3]);
+    (
+        start.map_or(1.0, |&x| slope(points[0], x)),
+        end.map_or(1.0, |&x| slope(x, points[3])),
+    )
+}
+
+/// A cursor over the little-endian fields of a VMD file.
+struct Bytes<'a>(&'a [u8]);
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], DeserializeError> {
+        if self.0.len() < n {
+            return Err(DeserializeError::Eof);
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32, DeserializeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn f32(&mut self) -> Result<f32, DeserializeError> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn vec3(&mut self) -> Result<[f32; 3], DeserializeError> {
+        Ok([self.f32()?, self.f32()?, self.f32()?])
+    }
+
+    /// Skips a section of `size`-byte records.
+    fn skip(&mut self, size: usize) -> Result<(), DeserializeError> {
+        let count = self.u32()? as usize;
+        self.take(count.saturating_mul(size)).map(drop)
+    }
+}
+
+fn read_cameras(bytes: &[u8]) -> Result<Vec<CameraFrame>, DeserializeError> {
+    let mut bytes = Bytes(bytes);
+    let magic = bytes.take(30)?;
+    let name_len = if magic.starts_with(b"Vocaloid Motion Data 0002") {
+        20
+    } else if magic.starts_with(b"Vocaloid Motion Data file") {
+        10
+    } else {
+        return Err(DeserializeError::Message("Not a VMD file".to_string()));
+    };
+    bytes.take(name_len)?;
+    bytes.skip(111)?; // bones
+    bytes.skip(23)?; // morphs
+
+    // Files from before cameras were added end here.
+    if bytes.0.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let count = bytes.u32()?;
+    let mut frames = Vec::new();
+    for _ in 0..count {
+        let frame = bytes.u32()?;
+        let distance = bytes.f32()?;
+        let position = bytes.vec3()?;
+        let rotation = bytes.vec3()?;
+        let mut interpolation = [[0; 4]; 6];
+        for (dst, src) in interpolation.iter_mut().zip(bytes.take(24)?.chunks(4)) {
+            dst.copy_from_slice(src);
+        }
+        let fov = bytes.u32()?;
+        bytes.take(1)?; // perspective, which A3DA cameras always are
+        frames.push(CameraFrame {
+            frame,
+            distance,
+            position,
+            rotation,
+            interpolation,
+            fov,
+        });
+    }
+    Ok(frames)
+}
+
+fn write_cameras(frames: &[CameraFrame]) -> Result<Vec<u8>, SerializeError> {
+    let mut bytes = b"Vocaloid Motion Data 0002".to_vec();
+    bytes.resize(30, 0);
+    let name = encode(CAMERA_MODEL, TextEncoding::ShiftJis)?;
+    bytes.extend_from_slice(&name);
+    bytes.resize(30 + 20, 0);
+    bytes.extend_from_slice(&[0; 8]); // bones, morphs
+    bytes.extend_from_slice(&(frames.len() as u32).to_le_bytes());
+    for frame in frames {
+        bytes.extend_from_slice(&frame.frame.to_le_bytes());
+        bytes.extend_from_slice(&frame.distance.to_le_bytes());
+        for x in frame.position.iter().chain(&frame.rotation) {
+            bytes.extend_from_slice(&x.to_le_bytes());
+        }
+        bytes.extend(frame.interpolation.iter().flatten());
+        bytes.extend_from_slice(&frame.fov.to_le_bytes());
+        bytes.push(0);
+    }
+    bytes.extend_from_slice(&[0; 8]); // lights, self shadows
+    Ok(bytes)
+}
+
+#[test]
+fn import_cameras() {
+    let linear = [LINEAR; 6];
+    let frame = |frame, distance, position, rotation, fov| CameraFrame {
+        frame,
+        distance,
+        position,
+        rotation,
+        interpolation: linear,
+        fov,
+    };
+    let half_pi = std::f32::consts::FRAC_PI_2;
+    let mut ease = linear;
+    ease[0] = [32, 96, 64, 127];
+    let frames = [
+        frame(0, -10.0, [0.0, 10.0, 0.0], [0.0, 0.0, 0.0], 30),
+        CameraFrame {
+            interpolation: ease,
+            ..frame(10, -10.0, [10.0, 10.0, 0.0], [0.0, half_pi, 0.1], 30)
+        },
+        frame(11, -20.0, [10.0, 10.0, 0.0], [0.0, half_pi, 0.1], 45),
+    ];
+    let a3da = from_vmd(&write_cameras(&frames).unwrap(), &VmdOptions::new()).unwrap();
+    assert_eq!(a3da.play_control.size, 23.0);
+    let camera = &a3da.camera_root[0];
+    let view_point = &camera.view_point;
+
+    // Leaving at twice the average slope of 0.04 per frame, arriving flat.
+    let x = camera.interest.trans.x.keys();
+    assert_eq!(
+        x.iter().map(Key::frame).collect::<Vec<_>>(),
+        [0.0, 20.0, 21.0, 22.0]
+    );
+    assert!((x[0].tangents().1 - 0.08).abs() < 1e-6);
+    assert_eq!(x[1].tangents(), (0.0, 0.0));
+    assert_eq!(
+        camera.interest.trans.y,
+        Curve::Static { value: 10.0 * 0.08 }
+    );
+    // The cut from frame 10 to 11 holds until the frame before.
+    let fov = view_point.fov.as_ref().unwrap();
+    assert_eq!(fov.value_at(21.0), Some(30f32.to_radians()));
+    assert_eq!(fov.value_at(22.0), Some(45f32.to_radians()));
+    assert_eq!(view_point.roll.value_at(20.0), Some(0.1));
+
+    // MMD looks down +Z from -Z, which is down -Z from +Z here; a quarter
+    // turn in MMD's yaw puts the eye on -X.
+    let eye = |frame| {
+        let v = &view_point.trans;
+        [&v.x, &v.y, &v.z].map(|x| x.value_at(frame).unwrap())
+    };
+    let close = |a: [f32; 3], b: [f32; 3]| a.iter().zip(&b).all(|(a, b)| (a - b).abs() < 1e-5);
+    assert!(close(eye(0.0), [0.0, 0.8, 0.8]));
+    assert!(close(eye(20.0), [0.0, 0.8, 0.0]));
+    assert!(close(eye(22.0), [-0.8, 0.8, 0.0]));
+
+    let text = crate::to_string(&a3da).unwrap();
+    assert_eq!(crate::from_str::<A3da>(&text).unwrap(), a3da);
+
+    assert!(from_vmd(b"Vocaloid Motion Data 0002", &VmdOptions::new()).is_err());
+    assert!(from_vmd(&write_cameras(&[]).unwrap(), &VmdOptions::new()).is_err());
+}
+
+#[test]
+fn export_cameras() {
+    let frame = |frame, distance, position, rotation| CameraFrame {
+        frame,
+        distance,
+        position,
+        rotation,
+        interpolation: [LINEAR; 6],
+        fov: 30,
+    };
+    let frames = [
+        frame(0, -40.0, [0.0, 10.0, 0.0], [0.1, 0.0, 0.0]),
+        frame(30, -40.0, [10.0, 10.0, 5.0], [0.1, 3.0, 0.2]),
+        frame(60, -20.0, [10.0, 15.0, 5.0], [-0.2, 3.5, 0.0]),
+    ];
+    let options = VmdOptions::new();
+    let a3da = from_vmd(&write_cameras(&frames).unwrap(), &options).unwrap();
+    let exported = read_cameras(&to_vmd(&a3da, &options).unwrap()).unwrap();
+    assert_eq!(
+        exported.iter().map(|x| x.frame).collect::<Vec<_>>(),
+        [0, 30, 60]
+    );
+    for (a, b) in frames.iter().zip(&exported) {
+        let close = |a: &[f32], b: &[f32]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-3);
+        assert!(close(&a.position, &b.position), "{:?} {:?}", a, b);
+        assert!(close(&a.rotation, &b.rotation), "{:?} {:?}", a, b);
+        assert!((a.distance - b.distance).abs() < 1e-3);
+        assert_eq!(b.fov, 30);
+    }
+
+    // 9301 frames at 60 fps are 4651 at 30, most of which are dropped.
+    let input = std::fs::read_to_string("assets/CAMPV001_BASE.a3da").unwrap();
+    let a3da: A3da = crate::from_str(&input).unwrap();
+    let exported = read_cameras(&to_vmd(&a3da, &options).unwrap()).unwrap();
+    assert_eq!(exported.last().unwrap().frame, 4650);
+    assert!(exported.len() < 4651 / 2);
+    assert!(exported.windows(2).all(|x| {
+        let delta = x[1].rotation[1] - x[0].rotation[1];
+        delta.abs() < PI
+    }));
+}
@@ -0,0 +1,50 @@
+//! YAML export for reviewing A3DA data by eye.
+//!
+//! Works with the typed `A3da` model, where curves and keys read as small
+//! nested maps, and with the dynamic `Value` tree. Edited YAML reads back into
+//! either, ready for `to_string_with`.
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::error::{DeserializeError, SerializeError};
+
+pub fn to_yaml<T>(value: &T) -> Result<String, SerializeError>
+where
+    T: Serialize + ?Sized,
+{
+    serde_yaml::to_string(value).map_err(|err| SerializeError::Message(err.to_string()))
+}
+
+pub fn from_yaml<T>(input: &str) -> Result<T, DeserializeError>
+where
+    T: DeserializeOwned,
+{
+    serde_yaml::from_str(input).map_err(|err| DeserializeError::Message(err.to_string()))
+}
+
+#[test]
+fn yaml_round_trip() {
+    use crate::model::{A3da, Curve};
+    use crate::{from_str, Value};
+
+    let input = std::fs::read_to_string("assets/CAMPV001_BASE.a3da").unwrap();
+    let a3da: A3da = from_str(&input).unwrap();
+    let yaml = to_yaml(&a3da).unwrap();
+    assert!(yaml.contains("    fov: !Static\n      value: 0.93616\n"));
+    let mut edited: A3da = from_yaml(&yaml.replace("0.93616", "0.8")).unwrap();
+    assert_eq!(
+        edited.camera_root[0].view_point.fov,
+        Some(Curve::Static { value: 0.8 })
+    );
+    edited.camera_root[0].view_point.fov = a3da.camera_root[0].view_point.fov.clone();
+    assert_eq!(edited, a3da);
+
+    let value = Value::parse(&input).unwrap();
+    let yaml = to_yaml(&value).unwrap();
+    assert_eq!(from_yaml::<Value>(&yaml).unwrap(), value);
+    let value: Value = from_yaml("a:\n  b: 1.5\n  c: [x, y]\n").unwrap();
+    assert_eq!(
+        crate::to_string(&value).unwrap(),
+        "a.b=1.5\na.c.0=x\na.c.1=y\na.c.length=2\n"
+    );
+}